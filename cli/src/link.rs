@@ -0,0 +1,153 @@
+//! Packet link to the board over any byte stream, normally the USB serial port.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use beginstm_shared::protocol::{self, Decoder, Encoder, Message, NackReason, Packet, MAX_FRAME_LEN};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Serial(serialport::Error),
+    /// No reply arrived in time.
+    Timeout,
+    /// The board refused the command.
+    Nack(NackReason),
+    /// The board answered with something that doesn't fit the command.
+    Unexpected(Message),
+    Protocol(protocol::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Serial(e) => write!(f, "serial port error: {}", e),
+            Error::Timeout => write!(f, "no reply from the board"),
+            Error::Nack(reason) => write!(f, "board refused the command: {:?}", reason),
+            Error::Unexpected(message) => write!(f, "unexpected reply: {:?}", message),
+            Error::Protocol(e) => write!(f, "protocol error: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Self {
+        Error::Serial(e)
+    }
+}
+
+impl From<protocol::Error> for Error {
+    fn from(e: protocol::Error) -> Self {
+        Error::Protocol(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Sends commands and collects packets.  `T` is the serial port, or anything else that reads
+/// with a timeout (returning `TimedOut` or `WouldBlock` when nothing arrives).
+pub struct Link<T> {
+    port: T,
+    encoder: Encoder,
+    decoder: Decoder,
+    pending: VecDeque<Packet>,
+    started: Instant,
+    bad_frames: u32,
+    pub timeout: Duration,
+}
+
+impl<T: Read + Write> Link<T> {
+    pub fn new(port: T) -> Self {
+        Link {
+            port,
+            encoder: Encoder::new(),
+            decoder: Decoder::new(),
+            pending: VecDeque::new(),
+            started: Instant::now(),
+            bad_frames: 0,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Send one message.  Returns its sequence number.
+    pub fn send(&mut self, message: Message) -> Result<u16> {
+        let seq = self.encoder.next_seq();
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let timestamp = self.started.elapsed().as_millis() as u32;
+        let len = self.encoder.encode(message, timestamp, &mut frame)?;
+        self.port.write_all(&frame[..len])?;
+        self.port.flush()?;
+        Ok(seq)
+    }
+
+    /// Wait up to `timeout` for the next good packet.  Returns `None` on timeout.
+    pub fn recv(&mut self) -> Result<Option<Packet>> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(packet) = self.pending.pop_front() {
+                return Ok(Some(packet));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+
+            let mut buf = [0u8; 64];
+            let len = match self.port.read(&mut buf) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for &byte in &buf[..len] {
+                match self.decoder.push(byte) {
+                    Some(Ok(packet)) => self.pending.push_back(packet),
+                    Some(Err(_)) => self.bad_frames += 1,
+                    None => (),
+                }
+            }
+        }
+    }
+
+    /// Send a command and wait for its reply, handing any telemetry that arrives meanwhile
+    /// to `other`.  A `Nack` comes back as `Error::Nack`.
+    pub fn request(&mut self, command: Message, mut other: impl FnMut(&Packet)) -> Result<Message> {
+        let seq = self.send(command)?;
+        let deadline = Instant::now() + self.timeout;
+        while Instant::now() < deadline {
+            let packet = match self.recv()? {
+                Some(packet) => packet,
+                None => break,
+            };
+            match packet.message {
+                Message::Nack { seq: s, reason } if s == seq => return Err(Error::Nack(reason)),
+                Message::Ack { seq: s } if s != seq => other(&packet), // Reply to an earlier command.
+                Message::Nack { .. } => other(&packet),
+                message if message.is_reply() => return Ok(message),
+                _ => other(&packet),
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Frames that failed to decode so far.
+    pub fn bad_frames(&self) -> u32 {
+        self.bad_frames
+    }
+
+    /// Packets the board sent that never arrived, judging by sequence numbers.
+    pub fn lost(&self) -> u32 {
+        self.decoder.lost()
+    }
+}
@@ -0,0 +1,381 @@
+//! Host companion for the beginstm firmware.
+//!
+//! Opens the board's USB serial port (usually `/dev/ttyACM0` on Linux), sends one command and
+//! prints the decoded reply, or prints the telemetry stream.
+//!
+//! ``` text
+//! $ beginstm-cli /dev/ttyACM0 scan
+//! $ beginstm-cli /dev/ttyACM0 led 4 on
+//! $ beginstm-cli /dev/ttyACM0 rate 25
+//! $ beginstm-cli /dev/ttyACM0 config
+//! $ beginstm-cli /dev/ttyACM0 time set
+//! $ beginstm-cli /dev/ttyACM0 monitor
+//! ```
+
+use std::io::{self, Write};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+use beginstm_shared::calendar::DateTime;
+use beginstm_shared::protocol::{Message, Packet};
+
+mod link;
+#[cfg(test)]
+mod sim;
+
+use link::{Link, Result};
+
+const USAGE: &str = "\
+usage: beginstm-cli <port> <command>
+
+commands:
+  scan                 list the devices on the I2C bus
+  led <0-7> <on|off>   switch a compass LED (0 = PE8, 1 = PE9, ... 7 = PE15)
+  rate <hz>            set the sensor sample rate (1-100 Hz)
+  config               show the board's settings
+  time                 show the board's date and time (UTC)
+  time set [unix]      set the board's clock from this computer's, or to <unix> seconds
+  monitor [count]      print telemetry, forever or for <count> packets";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Scan,
+    Led { led: u8, on: bool },
+    Rate { hz: u16 },
+    Config,
+    Time,
+    SetTime { unix: Option<u32> },
+    Monitor { count: Option<usize> },
+}
+
+/// Parse everything after the port name.
+fn parse_command(args: &[String]) -> std::result::Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["scan"] => Ok(Command::Scan),
+        ["led", led, state] => {
+            let led = led
+                .parse()
+                .ok()
+                .filter(|&led: &u8| led < 8)
+                .ok_or_else(|| format!("bad LED number '{}'", led))?;
+            let on = match *state {
+                "on" => true,
+                "off" => false,
+                other => return Err(format!("LED state must be 'on' or 'off', not '{}'", other)),
+            };
+            Ok(Command::Led { led, on })
+        }
+        ["rate", hz] => {
+            let hz = hz.parse().map_err(|_| format!("bad sample rate '{}'", hz))?;
+            Ok(Command::Rate { hz })
+        }
+        ["config"] => Ok(Command::Config),
+        ["time"] => Ok(Command::Time),
+        ["time", "set"] => Ok(Command::SetTime { unix: None }),
+        ["time", "set", unix] => {
+            let unix = unix.parse().map_err(|_| format!("bad Unix time '{}'", unix))?;
+            Ok(Command::SetTime { unix: Some(unix) })
+        }
+        ["monitor"] => Ok(Command::Monitor { count: None }),
+        ["monitor", count] => {
+            let count = count.parse().map_err(|_| format!("bad packet count '{}'", count))?;
+            Ok(Command::Monitor { count: Some(count) })
+        }
+        [] => Err("no command given".into()),
+        [other, ..] => Err(format!("unknown command or wrong arguments for '{}'", other)),
+    }
+}
+
+/// Open the serial port.  The baud rate is irrelevant to a USB CDC device but must be valid.
+fn open(path: &str) -> Result<Link<Box<dyn serialport::SerialPort>>> {
+    let port = serialport::new(path, 115_200)
+        .timeout(Duration::from_millis(50))
+        .open()?;
+    Ok(Link::new(port))
+}
+
+/// Works out the board's date and time for any packet from the last `Status` that carried one.
+#[derive(Default)]
+struct WallClock {
+    /// Board timestamp and Unix time of that `Status`.
+    anchor: Option<(u32, u32)>,
+}
+
+impl WallClock {
+    /// Take note of `packet` if it sets the time, and return the packet's time in Unix
+    /// milliseconds if known.  Only good to the second, since the board's clock is.
+    fn time_of(&mut self, packet: &Packet) -> Option<u64> {
+        if let Message::Status(status) = packet.message {
+            if status.time != 0 {
+                self.anchor = Some((packet.timestamp_ms, status.time));
+            }
+        }
+        let (anchor_ms, unix) = self.anchor?;
+        let since = packet.timestamp_ms.wrapping_sub(anchor_ms) as i32 as i64;
+        Some((unix as i64 * 1000 + since).max(0) as u64)
+    }
+}
+
+/// Format one telemetry packet as a line of text, or return the text of a `Log` packet as is.
+/// Packets are stamped with the date and time once `clock` knows it, else with the uptime.
+fn format_packet(packet: &Packet, clock: &mut WallClock) -> String {
+    let stamp = match clock.time_of(packet) {
+        Some(ms) => format!(
+            "{}.{:03} #{:05}",
+            DateTime::from_unix((ms / 1000) as u32),
+            ms % 1000,
+            packet.seq
+        ),
+        None => format!(
+            "{:7}.{:03} #{:05}",
+            packet.timestamp_ms / 1000,
+            packet.timestamp_ms % 1000,
+            packet.seq
+        ),
+    };
+    match packet.message {
+        Message::Accel(v) => format!("{} accel  x={:6} y={:6} z={:6}\n", stamp, v.x, v.y, v.z),
+        Message::Mag(v) => format!("{} mag    x={:6} y={:6} z={:6}\n", stamp, v.x, v.y, v.z),
+        Message::Button { pressed } => {
+            format!("{} button {}\n", stamp, if pressed { "pressed" } else { "released" })
+        }
+        Message::Status(s) => format!(
+            "{} status dropped={} sensor_errors={}\n",
+            stamp, s.dropped, s.sensor_errors
+        ),
+        Message::Log(text) => text.as_str().to_string(),
+        other => format!("{} {:?}\n", stamp, other),
+    }
+}
+
+/// Run one command, writing the results to `out`.
+fn run<T: io::Read + io::Write>(link: &mut Link<T>, command: Command, out: &mut impl Write) -> Result<()> {
+    let ignore = |_: &Packet| ();
+    match command {
+        Command::Scan => match link.request(Message::ScanI2c, ignore)? {
+            Message::I2cDevices(map) => {
+                // Same layout as the scan the firmware prints at boot.
+                for addr in 0x00_u8..0x80_u8 {
+                    if map.contains(addr) {
+                        write!(out, "{:02x} ", addr)?;
+                    } else {
+                        write!(out, ".. ")?;
+                    }
+                    if addr % 0x10 == 0x0f {
+                        writeln!(out)?;
+                    }
+                }
+            }
+            other => return Err(link::Error::Unexpected(other)),
+        },
+        Command::Led { led, on } => match link.request(Message::SetLed { led, on }, ignore)? {
+            Message::Ack { .. } => writeln!(out, "LED {} {}", led, if on { "on" } else { "off" })?,
+            other => return Err(link::Error::Unexpected(other)),
+        },
+        Command::Rate { hz } => match link.request(Message::SetSampleRate { hz }, ignore)? {
+            Message::Ack { .. } => writeln!(out, "sample rate {} Hz", hz)?,
+            other => return Err(link::Error::Unexpected(other)),
+        },
+        Command::Config => match link.request(Message::ReadConfig, ignore)? {
+            Message::Config(config) => {
+                writeln!(out, "sample rate: {} Hz", config.sample_rate_hz)?;
+                write!(out, "LEDs on:")?;
+                for led in (0..8).filter(|led| config.leds & (1 << led) != 0) {
+                    write!(out, " {}", led)?;
+                }
+                writeln!(out)?;
+            }
+            other => return Err(link::Error::Unexpected(other)),
+        },
+        Command::Time => match link.request(Message::ReadTime, ignore)? {
+            Message::Time { unix: 0 } => writeln!(out, "clock not set")?,
+            Message::Time { unix } => writeln!(out, "{} UTC", DateTime::from_unix(unix))?,
+            other => return Err(link::Error::Unexpected(other)),
+        },
+        Command::SetTime { unix } => {
+            let unix = unix.unwrap_or_else(|| {
+                let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+                now.as_secs() as u32
+            });
+            match link.request(Message::SetTime { unix }, ignore)? {
+                Message::Ack { .. } => writeln!(out, "time set to {} UTC", DateTime::from_unix(unix))?,
+                other => return Err(link::Error::Unexpected(other)),
+            }
+        }
+        Command::Monitor { count } => {
+            let mut clock = WallClock::default();
+            let mut seen = 0;
+            while count.is_none_or(|count| seen < count) {
+                if let Some(packet) = link.recv()? {
+                    write!(out, "{}", format_packet(&packet, &mut clock))?;
+                    out.flush()?;
+                    seen += 1;
+                }
+            }
+            writeln!(out, "-- lost {} packets, {} bad frames", link.lost(), link.bad_frames())?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (path, command) = match args.split_first() {
+        Some((path, rest)) if path != "-h" && path != "--help" => match parse_command(rest) {
+            Ok(command) => (path, command),
+            Err(e) => {
+                eprintln!("error: {}\n\n{}", e, USAGE);
+                process::exit(2);
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = open(path).and_then(|mut link| run(&mut link, command, &mut io::stdout()));
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use beginstm_shared::protocol::{NackReason, Status};
+    use sim::SimBoard;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    fn run_on(board: &SimBoard, command: &str) -> (Result<()>, String) {
+        let mut link = open(&board.path).unwrap();
+        let mut out = Vec::new();
+        let result = run(&mut link, parse_command(&args(command)).unwrap(), &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse_command(&args("scan")), Ok(Command::Scan));
+        assert_eq!(parse_command(&args("led 7 on")), Ok(Command::Led { led: 7, on: true }));
+        assert_eq!(parse_command(&args("led 0 off")), Ok(Command::Led { led: 0, on: false }));
+        assert_eq!(parse_command(&args("rate 25")), Ok(Command::Rate { hz: 25 }));
+        assert_eq!(parse_command(&args("config")), Ok(Command::Config));
+        assert_eq!(parse_command(&args("time")), Ok(Command::Time));
+        assert_eq!(parse_command(&args("time set")), Ok(Command::SetTime { unix: None }));
+        assert_eq!(
+            parse_command(&args("time set 1709211909")),
+            Ok(Command::SetTime { unix: Some(1_709_211_909) })
+        );
+        assert_eq!(parse_command(&args("monitor")), Ok(Command::Monitor { count: None }));
+        assert_eq!(parse_command(&args("monitor 5")), Ok(Command::Monitor { count: Some(5) }));
+
+        assert!(parse_command(&args("led 8 on")).is_err());
+        assert!(parse_command(&args("led 1 dim")).is_err());
+        assert!(parse_command(&args("rate fast")).is_err());
+        assert!(parse_command(&args("scan now")).is_err());
+        assert!(parse_command(&args("time set noon")).is_err());
+        assert!(parse_command(&args("")).is_err());
+    }
+
+    #[test]
+    fn scan_over_pty() {
+        let board = SimBoard::start(0);
+        let (result, out) = run_on(&board, "scan");
+        result.unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[1].starts_with(".. .. .. .. .. .. .. .. .. 19 "));
+        assert!(lines[1].contains(" 1e "));
+        assert!(!lines[0].contains(|c: char| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn led_and_rate_show_up_in_config() {
+        let board = SimBoard::start(0);
+        assert_eq!(run_on(&board, "led 6 on").1, "LED 6 on\n");
+        assert_eq!(run_on(&board, "led 4 on").1, "LED 4 on\n");
+        assert_eq!(run_on(&board, "rate 50").1, "sample rate 50 Hz\n");
+        let (result, out) = run_on(&board, "config");
+        result.unwrap();
+        assert_eq!(out, "sample rate: 50 Hz\nLEDs on: 4 6\n");
+    }
+
+    #[test]
+    fn refused_commands_are_errors() {
+        let board = SimBoard::start(0);
+        match run_on(&board, "led 5 on").0 {
+            Err(link::Error::Nack(NackReason::Busy)) => (),
+            other => panic!("expected Busy, got {:?}", other),
+        }
+        match run_on(&board, "rate 1000").0 {
+            Err(link::Error::Nack(NackReason::BadArgument)) => (),
+            other => panic!("expected BadArgument, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn monitor_prints_telemetry() {
+        let board = SimBoard::start(4);
+        let (result, out) = run_on(&board, "monitor 4");
+        result.unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "      1.234 #00000 accel  x=     0 y=    -1 z= 16384");
+        assert!(lines[1].contains("mag    x=   100 y=   200 z=  -300"));
+        assert!(lines[2].contains("status dropped=0 sensor_errors=0"));
+        assert!(lines[3].contains("#00003 accel"));
+        assert_eq!(lines[4], "-- lost 0 packets, 0 bad frames");
+    }
+
+    #[test]
+    fn silent_board_times_out() {
+        let board = SimBoard::silent();
+        let mut link = open(&board.path).unwrap();
+        link.timeout = Duration::from_millis(200);
+        match run(&mut link, Command::Config, &mut Vec::new()) {
+            Err(link::Error::Timeout) => (),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn log_text_passes_through() {
+        let packet = Packet {
+            seq: 0,
+            timestamp_ms: 0,
+            message: Message::Log("Hello, big world!\n".into()),
+        };
+        assert_eq!(format_packet(&packet, &mut WallClock::default()), "Hello, big world!\n");
+    }
+
+    #[test]
+    fn time_set_and_read() {
+        let board = SimBoard::start(0);
+        assert_eq!(run_on(&board, "time").1, "clock not set\n");
+        assert_eq!(run_on(&board, "time set 1709211909").1, "time set to 2024-02-29 13:05:09 UTC\n");
+        assert_eq!(run_on(&board, "time").1, "2024-02-29 13:05:09 UTC\n");
+        match run_on(&board, "time set 0").0 {
+            Err(link::Error::Nack(NackReason::BadArgument)) => (),
+            other => panic!("expected BadArgument, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn packets_get_wall_clock_stamps_after_a_timed_status() {
+        let packet = |timestamp_ms, message| Packet { seq: 1, timestamp_ms, message };
+        let accel = Message::Accel(Default::default());
+        let mut clock = WallClock::default();
+        assert!(format_packet(&packet(5_000, accel), &mut clock).starts_with("      5.000 #00001 accel"));
+        let status = Message::Status(Status { dropped: 0, sensor_errors: 0, time: 1_709_211_909 });
+        assert!(format_packet(&packet(6_000, status), &mut clock).starts_with("2024-02-29 13:05:09.000 #00001 status"));
+        assert!(format_packet(&packet(7_250, accel), &mut clock).starts_with("2024-02-29 13:05:10.250 #00001 accel"));
+        // Packets sent before the status but received after it.
+        assert!(format_packet(&packet(5_900, accel), &mut clock).starts_with("2024-02-29 13:05:08.900 #00001 accel"));
+    }
+}
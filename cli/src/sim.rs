@@ -0,0 +1,155 @@
+//! A pretend board on the master side of a pseudo-terminal, so the CLI can be tested end to end
+//! through a real tty without hardware.  It answers commands the way the firmware does.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serialport::{SerialPort, TTYPort};
+
+use beginstm_shared::calendar::DateTime;
+use beginstm_shared::protocol::{
+    Config, Decoder, Encoder, I2cMap, Message, NackReason, Status, Vector3, MAX_FRAME_LEN,
+};
+
+/// LEDs the firmware uses for other things: PE8 to PE11 (TIM1 PWM) and PE13 (the South blink).
+const BUSY_LEDS: u8 = 0b0010_1111;
+
+pub struct SimBoard {
+    /// Path of the slave tty for the CLI to open.
+    pub path: String,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    _slave: TTYPort,
+}
+
+impl SimBoard {
+    /// Start a board that answers commands.  `telemetry` extra packets are streamed first.
+    pub fn start(telemetry: usize) -> Self {
+        Self::spawn(true, telemetry)
+    }
+
+    /// Start a board that never answers.
+    pub fn silent() -> Self {
+        Self::spawn(false, 0)
+    }
+
+    fn spawn(answer: bool, telemetry: usize) -> Self {
+        let (mut master, slave) = TTYPort::pair().expect("can't create pseudo-terminal");
+        let path = slave.name().expect("pty has no name");
+        master.set_timeout(Duration::from_millis(20)).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || run(master, stop, answer, telemetry))
+        };
+
+        SimBoard {
+            path,
+            stop,
+            thread: Some(thread),
+            _slave: slave,
+        }
+    }
+}
+
+impl Drop for SimBoard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+struct State {
+    encoder: Encoder,
+    config: Config,
+    devices: I2cMap,
+    /// Unix time the clock was set to, 0 if it hasn't been.  It doesn't tick.
+    time: u32,
+}
+
+impl State {
+    fn send(&mut self, port: &mut TTYPort, message: Message) {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let len = self.encoder.encode(message, 1234, &mut frame).unwrap();
+        port.write_all(&frame[..len]).ok();
+    }
+
+    fn reply(&mut self, seq: u16, command: Message) -> Option<Message> {
+        let nack = |reason| Some(Message::Nack { seq, reason });
+        match command {
+            Message::ScanI2c => Some(Message::I2cDevices(self.devices)),
+            Message::ReadConfig => Some(Message::Config(self.config)),
+            Message::SetLed { led, .. } if led > 7 => nack(NackReason::BadArgument),
+            Message::SetLed { led, .. } if BUSY_LEDS & (1 << led) != 0 => nack(NackReason::Busy),
+            Message::SetLed { led, on } => {
+                if on {
+                    self.config.leds |= 1 << led;
+                } else {
+                    self.config.leds &= !(1 << led);
+                }
+                Some(Message::Ack { seq })
+            }
+            Message::SetSampleRate { hz } if hz == 0 || hz > 100 => nack(NackReason::BadArgument),
+            Message::SetSampleRate { hz } => {
+                self.config.sample_rate_hz = hz;
+                Some(Message::Ack { seq })
+            }
+            Message::SetTime { unix } if !DateTime::from_unix(unix).is_valid() => nack(NackReason::BadArgument),
+            Message::SetTime { unix } => {
+                self.time = unix;
+                Some(Message::Ack { seq })
+            }
+            Message::ReadTime => Some(Message::Time { unix: self.time }),
+            _ => nack(NackReason::Unsupported),
+        }
+    }
+}
+
+fn run(mut port: TTYPort, stop: Arc<AtomicBool>, answer: bool, telemetry: usize) {
+    let mut devices = I2cMap::default();
+    devices.insert(0x19); // LSM303AGR accelerometer
+    devices.insert(0x1e); // LSM303AGR magnetometer
+    let mut state = State {
+        encoder: Encoder::new(),
+        config: Config { sample_rate_hz: 10, leds: 0 },
+        devices,
+        time: 0,
+    };
+
+    for i in 0..telemetry {
+        let message = match i % 3 {
+            0 => Message::Accel(Vector3 { x: i as i16, y: -1, z: 16384 }),
+            1 => Message::Mag(Vector3 { x: 100, y: 200, z: -300 }),
+            _ => Message::Status(Status { dropped: 0, sensor_errors: 0, time: 0 }),
+        };
+        state.send(&mut port, message);
+    }
+
+    let mut decoder = Decoder::new();
+    let mut buf = [0u8; 64];
+    while !stop.load(Ordering::Relaxed) {
+        let len = match port.read(&mut buf) {
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(_) => break,
+        };
+        for &byte in &buf[..len] {
+            if let Some(Ok(packet)) = decoder.push(byte) {
+                if !answer {
+                    continue;
+                }
+                // Chatter in front of the reply, like the firmware's sensor stream.
+                state.send(&mut port, Message::Button { pressed: true });
+                if let Some(reply) = state.reply(packet.seq, packet.message) {
+                    state.send(&mut port, reply);
+                }
+            }
+        }
+    }
+}
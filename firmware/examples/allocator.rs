@@ -1,15 +1,18 @@
 //! How to use the heap and a dynamic memory allocator
 //!
-//! This example depends on the alloc-cortex-m crate so you'll have to add it to your Cargo.toml:
+//! This example depends on the linked_list_allocator crate, which the `allocator` feature
+//! brings in.  It used to use alloc-cortex-m, a wrapper round the same allocator that needs a
+//! nightly compiler for its `#[alloc_error_handler]`:
 //!
 //! ``` text
-//! # or edit the Cargo.toml file manually
-//! $ cargo add alloc-cortex-m
+//! $ cargo build --example allocator --features allocator
 //! ```
 //!
+//! Running out of memory calls the default allocation error handler, which panics.
+//! The firmware's own heap, in src/heap.rs, adds usage statistics and logs failed allocations.
+//!
 //! ---
 
-#![feature(alloc_error_handler)]
 #![no_main]
 #![no_std]
 
@@ -17,23 +20,21 @@ extern crate alloc;
 use panic_halt as _;
 
 use self::alloc::vec;
-use core::alloc::Layout;
 
-use alloc_cortex_m::CortexMHeap;
-use cortex_m::asm;
 use cortex_m_rt::entry;
+use linked_list_allocator::LockedHeap;
 use cortex_m_semihosting::{hprintln, debug};
 
 // this is the allocator the application will use
 #[global_allocator]
-static ALLOCATOR: CortexMHeap = CortexMHeap::empty();
+static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
 const HEAP_SIZE: usize = 1024; // in bytes
 
 #[entry]
 fn main() -> ! {
     // Initialize the allocator BEFORE you use it
-    unsafe { ALLOCATOR.init(cortex_m_rt::heap_start() as usize, HEAP_SIZE) }
+    unsafe { ALLOCATOR.lock().init(cortex_m_rt::heap_start() as *mut u8, HEAP_SIZE) }
 
     // Growable array allocated on the heap
     let xs = vec![0, 1, 2];
@@ -46,11 +47,3 @@ fn main() -> ! {
 
     loop {}
 }
-
-// define what happens in an Out Of Memory (OOM) condition
-#[alloc_error_handler]
-fn alloc_error(_layout: Layout) -> ! {
-    asm::bkpt();
-
-    loop {}
-}
@@ -5,20 +5,14 @@
 //!
 //! [`svd2rust`]: https://crates.io/crates/svd2rust
 //!
-//! This example depends on the [`stm32f3`] crate so you'll have to
-//! uncomment it in your Cargo.toml.
+//! This example depends on the [`stm32f3`] crate, which the `device` feature brings in.  The
+//! HAL uses the same crate underneath, as `stm32f3xx_hal::pac`.
 //!
 //! [`stm32f3`]: https://crates.io/crates/stm32f3
 //!
+//! ``` text
+//! $ cargo build --example device --features device
 //! ```
-//! $ edit Cargo.toml && tail $_
-//! [dependencies.stm32f3]
-//! features = ["stm32f303", "rt"]
-//! version = "0.7.1"
-//! ```
-//!
-//! You also need to set the build target to thumbv7em-none-eabihf,
-//! typically by editing `.cargo/config` and uncommenting the relevant target line.
 //!
 //! ---
 
@@ -38,9 +32,8 @@ fn main() -> ! {
     let p = cortex_m::Peripherals::take().unwrap();
 
     let mut syst = p.SYST;
-    let mut nvic = p.NVIC;
 
-    nvic.enable(Interrupt::EXTI0);
+    unsafe { NVIC::unmask(Interrupt::EXTI0) };
 
     // configure the system timer to wrap around every second
     syst.set_clock_source(SystClkSource::Core);
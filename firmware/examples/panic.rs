@@ -0,0 +1,41 @@
+//! Changing the panicking behavior
+//!
+//! The easiest way to change the panicking behavior is to use a different [panic handler crate][0].
+//! Here a feature picks the handler, so no code or Cargo.toml needs editing:
+//!
+//! ``` text
+//! $ cargo build --example panic                               # panic-halt
+//! $ cargo build --example panic --features panic-itm          # message over ITM
+//! $ cargo build --example panic --features panic-semihosting  # message on the host's stderr
+//! ```
+//!
+//! Each feature turns on the optional dependency of the same name, the panic-itm or
+//! panic-semihosting crate, so only the handler in use is built.  Pick one at most.  The
+//! firmware itself has a handler of its own, in src/panic.rs.
+//!
+//! [0]: https://crates.io/keywords/panic-impl
+
+#![no_main]
+#![no_std]
+
+#[cfg(all(feature = "panic-itm", feature = "panic-semihosting"))]
+compile_error!("the panic-itm and panic-semihosting features each bring a panic handler; pick one");
+
+// `panic!` halts execution; the panic message is ignored
+#[cfg(not(any(feature = "panic-itm", feature = "panic-semihosting")))]
+use panic_halt as _;
+
+// Logs panic messages using the ITM (Instrumentation Trace Macrocell)
+#[cfg(feature = "panic-itm")]
+use panic_itm as _;
+
+// Reports panic messages to the host stderr using semihosting; needs a debugger attached
+#[cfg(feature = "panic-semihosting")]
+use panic_semihosting as _;
+
+use cortex_m_rt::entry;
+
+#[entry]
+fn main() -> ! {
+    panic!("Oops")
+}
@@ -9,12 +9,14 @@
 //!
 //! Running this example:
 //!
-//! Ensure there are no targets specified under `[build]` in `.cargo/config`
-//! In order to make this work, we lose the convenience of having a default target that isn't the
-//! host.
+//! `firmware/.cargo/config.toml` makes the MCU the default target, so ask for the host explicitly
+//! when testing:
 //!
-//! cargo build --example test_on_host --target thumbv7m-none-eabi
-//! cargo test --example test_on_host
+//! cargo build --example test_on_host
+//! cargo test --example test_on_host --target x86_64-unknown-linux-gnu
+//!
+//! Code that doesn't touch hardware is better off in the `shared` crate at the top of the
+//! workspace, where a plain `cargo test` runs its tests.
 
 #![cfg_attr(test, allow(unused_imports))]
 
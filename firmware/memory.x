@@ -8,8 +8,19 @@ MEMORY
   /* I changed them to those for STM32F303VC */
   FLASH : ORIGIN = 0x08000000, LENGTH = 256K
   RAM : ORIGIN = 0x20000000, LENGTH = 40K
+  /* Core-coupled memory: as fast as RAM, but out of the DMA controllers' reach. */
+  CCMRAM : ORIGIN = 0x10000000, LENGTH = 8K
 }
 
+/* Statics with `#[link_section = ".ccmram.NAME"]` go in CCM, left uninitialised like .uninit;
+   the heap does with the heap-ccm feature. */
+SECTIONS {
+  .ccmram (NOLOAD) : ALIGN(4) {
+    *(.ccmram .ccmram.*);
+    . = ALIGN(4);
+  } > CCMRAM
+} INSERT AFTER .bss;
+
 /* This is where the call stack will be allocated. */
 /* The stack is of the full descending type. */
 /* You may want to use this variable to locate the call stack and static
@@ -0,0 +1,94 @@
+//! LED animations on TIM1 channels 1 and 2: PE9 (red, North) and PE11 (green, East), with
+//! their complementary outputs PE8 (blue, North-West) and PE10 (orange, North-East) showing
+//! the inverse.
+//!
+//! TIM4 interrupts `FRAME_HZ` times a second; each time, the animator in the shared crate works
+//! out the next brightness of every channel and the duty registers are updated.  Application
+//! code starts patterns with `set` or `set_all` and can call them at any time.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{free, Mutex};
+
+use stm32f3xx_hal as hal;
+use hal::prelude::*;
+use hal::stm32::{self, interrupt};
+use hal::timer::{Event, Timer};
+
+use beginstm_shared::animation::{scale, Animator, Pattern};
+use crate::tim1::Channel;
+use crate::watchdog::{self, Task};
+
+/// Animation frames per second.  Fast enough that a fade has no visible steps.
+pub const FRAME_HZ: u32 = 100;
+const FRAME_MS: u32 = 1000 / FRAME_HZ;
+
+/// Channel numbers for `set`.
+pub const NORTH: usize = 0;
+pub const EAST: usize = 1;
+const CHANNELS: usize = 2;
+
+struct Engine {
+    channels: [Channel; CHANNELS],
+    timer: Timer<stm32::TIM4>,
+    animator: Animator<CHANNELS>,
+}
+
+static ENGINE: Mutex<RefCell<Option<Engine>>> = Mutex::new(RefCell::new(None));
+
+#[interrupt]
+// Frame timer: step every animation on by one frame.
+fn TIM4() {
+    free(|cs| {
+        if let Some(ref mut engine) = *ENGINE.borrow(cs).borrow_mut() {
+            engine.timer.clear_update_interrupt_flag();
+            let duties = engine.animator.advance(FRAME_MS);
+            for (channel, &duty) in engine.channels.iter_mut().zip(duties.iter()) {
+                channel.set_duty(scale(duty, channel.get_max_duty()));
+            }
+        }
+    });
+    watchdog::check_in(Task::Leds);
+}
+
+/// Take the `NORTH` and `EAST` channels and the frame timer, and start with every pattern off.
+/// The caller unmasks the TIM4 interrupt.
+pub fn init(mut channels: [Channel; CHANNELS], mut timer: Timer<stm32::TIM4>) {
+    for channel in channels.iter_mut() {
+        channel.set_duty(0);
+        channel.enable();
+    }
+    timer.start(FRAME_HZ.hz());
+    timer.listen(Event::Update);
+    free(|cs| {
+        ENGINE.borrow(cs).replace(Some(Engine {
+            channels,
+            timer,
+            animator: Animator::new(),
+        }));
+    });
+}
+
+/// Start `pattern` on one channel, `NORTH` or `EAST`.
+pub fn set(channel: usize, pattern: Pattern) {
+    with_animator(|animator| animator.set(channel, pattern));
+}
+
+/// Start `pattern` on both channels together.
+pub fn set_all(pattern: Pattern) {
+    with_animator(|animator| animator.set_all(pattern));
+}
+
+/// Scale every pattern down, 255 being full brightness.
+#[allow(dead_code)]
+pub fn set_brightness(brightness: u8) {
+    with_animator(|animator| animator.set_brightness(brightness));
+}
+
+fn with_animator(f: impl FnOnce(&mut Animator<CHANNELS>)) {
+    free(|cs| {
+        if let Some(ref mut engine) = *ENGINE.borrow(cs).borrow_mut() {
+            f(&mut engine.animator);
+        }
+    });
+}
@@ -0,0 +1,98 @@
+//! Clock profiles, and the frequencies that depend on them.
+//!
+//! The board can run from the internal 8 MHz HSI, or from the 8 MHz HSE (the ST-LINK's MCO
+//! output, hence bypass) through the PLL at 48 or 72 MHz.  Only the HSE profiles can run USB,
+//! which needs an exact 48 MHz: straight from the PLL at 48 MHz, or divided by 1.5 at 72 MHz.
+//!
+//! `Profile::DEFAULT` is picked at build time with the `clock-hsi-8mhz` or `clock-72mhz`
+//! feature, 48 MHz without either; main can also choose another at boot.  Nothing else assumes
+//! a frequency: timers, delays and the I2C and SWO baud rates all work from the `Clocks` that
+//! `Profile::freeze` returns.
+//!
+//! A timer runs at its APB clock, or at twice that when the APB is divided down from the AHB.
+//! The HAL's `Timer` and `pwm` always use the APB1 divider, which is wrong for the timers on
+//! APB2 whenever the two dividers differ, so use the functions here instead.
+
+use cortex_m::peripheral::TPIU;
+
+use stm32f3xx_hal as hal;
+use hal::flash::ACR;
+use hal::prelude::*;
+use hal::rcc::{Clocks, CFGR};
+use hal::stm32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    /// 8 MHz from the internal oscillator.  No USB.
+    Hsi8,
+    /// 48 MHz from the HSE through the PLL, APB1 at 24 MHz.
+    Hse48,
+    /// 72 MHz from the HSE through the PLL, APB1 at 36 MHz: as fast as the chip goes.
+    Hse72,
+}
+
+impl Profile {
+    /// The profile chosen by cargo features.
+    pub const DEFAULT: Profile = if cfg!(feature = "clock-hsi-8mhz") {
+        Profile::Hsi8
+    } else if cfg!(feature = "clock-72mhz") {
+        Profile::Hse72
+    } else {
+        Profile::Hse48
+    };
+
+    pub fn sysclk_hz(self) -> u32 {
+        match self {
+            Profile::Hsi8 => 8_000_000,
+            Profile::Hse48 => 48_000_000,
+            Profile::Hse72 => 72_000_000,
+        }
+    }
+
+    /// Set up the clocks, flash wait states included, and freeze them.
+    pub fn freeze(self, cfgr: CFGR, acr: &mut ACR) -> Clocks {
+        let cfgr = match self {
+            Profile::Hsi8 => cfgr.sysclk(8.mhz()),
+            Profile::Hse48 => cfgr.use_hse(8.mhz()).bypass_hse().sysclk(48.mhz()).pclk1(24.mhz()),
+            Profile::Hse72 => cfgr.use_hse(8.mhz()).bypass_hse().sysclk(72.mhz()).pclk1(36.mhz()),
+        };
+        let clocks = cfgr.freeze(acr);
+        debug_assert_eq!(clocks.sysclk().0, self.sysclk_hz());
+        debug_assert_eq!(clocks.usbclk_valid(), self != Profile::Hsi8);
+        clocks
+    }
+}
+
+/// SWO baud rate.  Debug probes capture at this rate; 2 MHz is the ST-LINK's default.
+pub const SWO_HZ: u32 = 2_000_000;
+
+/// Set the SWO (ITM output) baud rate divider for the core clock that's now running, since the
+/// one OpenOCD sets at connection is for the 8 MHz reset clock.  `tpiu` is from
+/// `cortex_m::Peripherals`; `dbgmcu` is the device's debug MCU block.
+pub fn init_swo(tpiu: &mut TPIU, dbgmcu: &stm32::DBGMCU, clocks: &Clocks) {
+    // Asynchronous trace on the SWO pin (PB3).
+    dbgmcu.cr.modify(|_, w| unsafe { w.trace_ioen().set_bit().trace_mode().bits(0b00) });
+    unsafe {
+        tpiu.sppr.write(2); // NRZ (UART-like) encoding.
+        tpiu.acpr.write(clocks.sysclk().0 / SWO_HZ - 1);
+        tpiu.ffcr.write(0x100); // Formatter off, as a single ITM stream needs.
+    }
+}
+
+/// Clock of the timers on APB1: TIM2-4, TIM6 and TIM7.
+pub fn apb1_timer_hz(clocks: &Clocks) -> u32 {
+    timer_hz(clocks.pclk1().0, clocks.hclk().0)
+}
+
+/// Clock of the timers on APB2: TIM1, TIM8 and TIM15-17.
+pub fn apb2_timer_hz(clocks: &Clocks) -> u32 {
+    timer_hz(clocks.pclk2().0, clocks.hclk().0)
+}
+
+fn timer_hz(pclk: u32, hclk: u32) -> u32 {
+    if pclk == hclk {
+        pclk
+    } else {
+        pclk * 2
+    }
+}
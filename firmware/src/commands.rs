@@ -0,0 +1,80 @@
+//! Commands from the host tool, received over USB serial and answered with telemetry replies.
+
+use beginstm_shared::calendar::DateTime;
+use beginstm_shared::protocol::{Config, Decoder, Message, NackReason, Packet};
+
+use cortex_m::interrupt::free;
+
+use crate::leds::Leds;
+use crate::rtc;
+use crate::sensor::Sensor;
+use crate::telemetry;
+use crate::usb_serial::USB_SERIAL;
+
+/// Reassembles command packets from the USB receive stream.
+pub struct CommandReader {
+    decoder: Decoder,
+}
+
+impl CommandReader {
+    pub const fn new() -> Self {
+        CommandReader {
+            decoder: Decoder::new(),
+        }
+    }
+
+    /// Read whatever the host has sent and carry out any complete commands.
+    pub fn poll(&mut self, sensor: &mut Sensor, leds: &mut Leds) {
+        let mut buf = [0u8; 64];
+        loop {
+            let len = free(|cs| {
+                USB_SERIAL
+                    .borrow(cs)
+                    .borrow_mut()
+                    .as_mut()
+                    .map_or(0, |serial| serial.read(&mut buf))
+            });
+            if len == 0 {
+                break;
+            }
+            for &byte in &buf[..len] {
+                // Frames that fail to decode are dropped; the host times out and retries.
+                if let Some(Ok(packet)) = self.decoder.push(byte) {
+                    telemetry::send(execute(&packet, sensor, leds));
+                }
+            }
+        }
+    }
+}
+
+/// Carry out one command and return the reply.
+fn execute(packet: &Packet, sensor: &mut Sensor, leds: &mut Leds) -> Message {
+    let seq = packet.seq;
+    let ack = |result: Result<(), NackReason>| match result {
+        Ok(()) => Message::Ack { seq },
+        Err(reason) => Message::Nack { seq, reason },
+    };
+
+    match packet.message {
+        Message::ScanI2c => Message::I2cDevices(sensor.scan()),
+        Message::SetLed { led, on } => ack(leds.set(led, on)),
+        Message::SetSampleRate { hz } => ack(if sensor.set_rate(hz) {
+            Ok(())
+        } else {
+            Err(NackReason::BadArgument)
+        }),
+        Message::ReadConfig => Message::Config(Config {
+            sample_rate_hz: sensor.rate_hz(),
+            leds: leds.state(),
+        }),
+        Message::SetTime { unix } => ack(rtc::set(&DateTime::from_unix(unix)).map_err(|_| NackReason::BadArgument)),
+        Message::ReadTime => Message::Time {
+            unix: rtc::now().map_or(0, |now| now.to_unix()),
+        },
+        // Telemetry and replies only go the other way.
+        _ => Message::Nack {
+            seq,
+            reason: NackReason::Unsupported,
+        },
+    }
+}
@@ -0,0 +1,97 @@
+//! Console output that goes to ITM stimulus port 0 and, when a host has it open, the USB serial
+//! port.
+//!
+//! Use `cprint!` and `cprintln!` the same way as `iprint!`/`iprintln!`, minus the stimulus port
+//! argument.  This module must be declared before the others so the macros are visible to them.
+//!
+//! On USB the text travels as telemetry `Log` packets, so it can share the port with sensor data.
+//! The `log-itm` and `log-usb` features, both on by default, pick the backends; with neither,
+//! console output goes nowhere.
+
+use core::fmt;
+#[cfg(feature = "log-usb")]
+use core::fmt::Write;
+
+#[cfg(feature = "log-itm")]
+use cortex_m::{interrupt::free, itm, peripheral::ITM};
+
+#[cfg(feature = "log-usb")]
+use beginstm_shared::protocol::{Message, Text};
+
+#[cfg(feature = "log-usb")]
+use crate::telemetry;
+
+macro_rules! cprint {
+    ($($arg:tt)*) => {
+        $crate::console::print(format_args!($($arg)*))
+    };
+}
+
+macro_rules! cprintln {
+    () => {
+        cprint!("\n")
+    };
+    ($fmt:expr) => {
+        cprint!(concat!($fmt, "\n"))
+    };
+    ($fmt:expr, $($arg:tt)*) => {
+        cprint!(concat!($fmt, "\n"), $($arg)*)
+    };
+}
+
+/// Backend for the `cprint!` and `cprintln!` macros.
+pub fn print(args: fmt::Arguments) {
+    #[cfg(feature = "log-itm")]
+    print_itm(args);
+    #[cfg(feature = "log-usb")]
+    print_usb(args);
+    #[cfg(not(any(feature = "log-itm", feature = "log-usb")))]
+    let _ = args;
+}
+
+#[cfg(feature = "log-itm")]
+fn print_itm(args: fmt::Arguments) {
+    // The ITM stimulus ports are write-only FIFOs; the critical section keeps an interrupt's
+    // output from landing in the middle of this message.
+    free(|_| {
+        let stim = unsafe { &mut (*ITM::PTR).stim[0] };
+        itm::write_fmt(stim, args);
+    });
+}
+
+#[cfg(feature = "log-usb")]
+fn print_usb(args: fmt::Arguments) {
+    let mut log = LogWriter(Text::new());
+    log.write_fmt(args).ok();
+    log.flush();
+}
+
+/// Cuts formatted text into `Log` packets.
+#[cfg(feature = "log-usb")]
+struct LogWriter(Text);
+
+#[cfg(feature = "log-usb")]
+impl LogWriter {
+    fn flush(&mut self) {
+        if !self.0.as_str().is_empty() {
+            telemetry::send(Message::Log(self.0));
+            self.0 = Text::new();
+        }
+    }
+}
+
+#[cfg(feature = "log-usb")]
+impl Write for LogWriter {
+    fn write_str(&mut self, mut s: &str) -> fmt::Result {
+        while !s.is_empty() {
+            let before = self.0.as_str().len();
+            self.0.write_str(s)?;
+            let taken = self.0.as_str().len() - before;
+            s = &s[taken..];
+            if !s.is_empty() {
+                self.flush();
+            }
+        }
+        Ok(())
+    }
+}
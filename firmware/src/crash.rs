@@ -0,0 +1,147 @@
+//! Fault handlers that save what happened and reset, for the next boot to report.
+//!
+//! The crash.rs example shows the crash only to an attached debugger.  Here the handlers fill
+//! in a `CrashRecord` (see `beginstm_shared::crash`) in the `.uninit` section, which the
+//! runtime leaves alone at start-up, and reset the chip; the panic handler in panic.rs saves
+//! its record through `save` too.  `take` hands the record to the next boot once and clears
+//! it.  The reset cause after a crash is `Software`.
+//!
+//! Out of reset every fault escalates to HardFault, and HFSR only says it was forced.  `init`
+//! turns on the separate MemManage, BusFault and UsageFault exceptions and the divide-by-zero
+//! trap, so each fault lands in a handler of its own; all of them save the fault status and
+//! address registers, which `beginstm_shared::fault` decodes for the report.  cortex-m-rt only
+//! passes the exception frame to HardFault, so the other three go through a few instructions
+//! of assembly that find it on whichever stack was in use.
+
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{exception, ExceptionFrame};
+
+use beginstm_shared::crash::{CrashRecord, Frame, Kind};
+
+const SHCSR_MEMFAULTENA: u32 = 1 << 16;
+const SHCSR_BUSFAULTENA: u32 = 1 << 17;
+const SHCSR_USGFAULTENA: u32 = 1 << 18;
+const CCR_DIV_0_TRP: u32 = 1 << 4;
+
+#[link_section = ".uninit.CRASH"]
+static mut RECORD: MaybeUninit<CrashRecord> = MaybeUninit::uninit();
+
+fn record_ptr() -> *mut CrashRecord {
+    // `MaybeUninit` has the layout of what it holds.
+    ptr::addr_of_mut!(RECORD).cast()
+}
+
+/// Give MemManage, BusFault and UsageFault their own handlers, and trap integer division by
+/// zero, which otherwise gives 0.
+pub fn init() {
+    // NOTE(unsafe) only this module touches these SHCSR and CCR bits, once, at start-up.
+    unsafe {
+        let scb = &*SCB::ptr();
+        scb.shcsr.modify(|shcsr| shcsr | SHCSR_MEMFAULTENA | SHCSR_BUSFAULTENA | SHCSR_USGFAULTENA);
+        scb.ccr.modify(|ccr| ccr | CCR_DIV_0_TRP);
+    }
+}
+
+/// Where the configurable fault handlers run, since the fault may be a stack overflow.
+const FAULT_STACK_SIZE: usize = 1024;
+#[no_mangle]
+static mut FAULT_STACK: [u64; FAULT_STACK_SIZE / 8] = [0; FAULT_STACK_SIZE / 8]; // 8-byte aligned.
+
+// The three configurable faults share one entry, which passes the stacked frame on: bit 2 of
+// the EXC_RETURN value in LR says whether the main or the process stack was in use.  Then it
+// moves to `FAULT_STACK`, because after an overflow the main stack is in the guard (see
+// `stack.rs`), where every push faults again.
+#[cfg(target_arch = "arm")]
+core::arch::global_asm!(
+    ".section .text.ConfigurableFault, \"ax\"",
+    ".global MemoryManagement",
+    ".global BusFault",
+    ".global UsageFault",
+    ".type MemoryManagement, %function",
+    ".type BusFault, %function",
+    ".type UsageFault, %function",
+    ".thumb_func",
+    "MemoryManagement:",
+    ".thumb_func",
+    "BusFault:",
+    ".thumb_func",
+    "UsageFault:",
+    "    tst lr, #4",
+    "    ite eq",
+    "    mrseq r0, MSP",
+    "    mrsne r0, PSP",
+    "    ldr r1, =FAULT_STACK",
+    "    add r1, r1, #{size}",
+    "    msr MSP, r1",
+    "    b.w configurable_fault",
+    size = const FAULT_STACK_SIZE,
+);
+
+#[no_mangle]
+extern "C" fn configurable_fault(ef: &ExceptionFrame) -> ! {
+    // NOTE(unsafe) reads only.
+    let active = unsafe { (*SCB::ptr()).icsr.read() } & 0x1ff;
+    let kind = match active {
+        4 => Kind::MemManage,
+        5 => Kind::BusFault,
+        _ => Kind::UsageFault,
+    };
+    save_and_reset(fault_record(kind, ef))
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    save_and_reset(fault_record(Kind::HardFault, ef))
+}
+
+/// The frame and the fault registers.
+fn fault_record(kind: Kind, ef: &ExceptionFrame) -> CrashRecord {
+    let mut record = CrashRecord::new(kind);
+    record.frame = Frame {
+        r0: ef.r0,
+        r1: ef.r1,
+        r2: ef.r2,
+        r3: ef.r3,
+        r12: ef.r12,
+        lr: ef.lr,
+        pc: ef.pc,
+        xpsr: ef.xpsr,
+    };
+    // NOTE(unsafe) reads only.
+    let scb = unsafe { &*SCB::ptr() };
+    record.cfsr = scb.cfsr.read();
+    record.hfsr = scb.hfsr.read();
+    record.mmfar = scb.mmfar.read();
+    record.bfar = scb.bfar.read();
+    record
+}
+
+fn save_and_reset(record: CrashRecord) -> ! {
+    save(record);
+    SCB::sys_reset()
+}
+
+/// Seal `record` and keep it for the next boot.  For handlers on their way to a reset or a
+/// halt, with interrupts disabled.
+pub fn save(mut record: CrashRecord) {
+    record.seal();
+    // NOTE(unsafe) nothing else runs by now, and the write is volatile so it isn't dropped
+    // for never being read.
+    unsafe { ptr::write_volatile(record_ptr(), record) };
+}
+
+/// The record of the crash that caused the last reset, if there was one.  Clears it.
+pub fn take() -> Option<CrashRecord> {
+    // NOTE(unsafe) the handlers only write the record on their way to a reset, and whatever is
+    // in RAM is a value of the type, valid record or not.
+    unsafe {
+        let record = ptr::read_volatile(record_ptr());
+        let mut cleared = record;
+        cleared.clear();
+        ptr::write_volatile(record_ptr(), cleared);
+        Some(record).filter(CrashRecord::is_valid)
+    }
+}
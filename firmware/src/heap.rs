@@ -0,0 +1,109 @@
+//! The heap, for `alloc`'s `Box`, `Vec` and the rest, with the `allocator` feature.
+//!
+//! The memory is a static array of `SIZE` bytes, in `.bss` like the other statics, so it sits
+//! below the stack's guard (see stack.rs) and a stack overflow can't reach it.  With the
+//! `heap-ccm` feature it goes in the 8 KB of core-coupled memory instead, which leaves the main
+//! RAM to the stack and the statics; the DMA controllers can't reach CCM, so nothing allocated
+//! there can be a DMA buffer.  `linked_list_allocator` does the allocating, inside a critical
+//! section so that interrupts may allocate too.
+//!
+//! `stats` reports the usage, the peak and how fragmented the free memory is.  An allocation
+//! that fails logs its size and alignment with the stats; then the default allocation error
+//! handler panics, and the panic handler saves the record and resets.
+//!
+//! Interrupt handlers that must finish on time should take fixed-size blocks from a
+//! `beginstm_shared::pool::Pool` instead, in constant time and without a critical section.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::RefCell;
+use core::mem::MaybeUninit;
+use core::ptr::{self, addr_of_mut, NonNull};
+
+use cortex_m::interrupt::{free, Mutex};
+use linked_list_allocator::Heap as Allocator;
+
+use beginstm_shared::heap::Stats;
+
+pub const SIZE: usize = 4096;
+
+#[cfg_attr(feature = "heap-ccm", link_section = ".ccmram.HEAP")]
+static mut MEMORY: [MaybeUninit<u8>; SIZE] = [MaybeUninit::uninit(); SIZE];
+
+struct State {
+    allocator: Allocator,
+    stats: Stats,
+}
+
+struct Heap(Mutex<RefCell<State>>);
+
+#[global_allocator]
+static HEAP: Heap = Heap(Mutex::new(RefCell::new(State {
+    allocator: Allocator::empty(),
+    stats: Stats { size: 0, used: 0, peak: 0, largest_free: 0, allocations: 0, failures: 0 },
+})));
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let block = free(|cs| {
+            let state = &mut *self.0.borrow(cs).borrow_mut();
+            let block = state.allocator.allocate_first_fit(layout);
+            state.stats.allocated(state.allocator.used(), block.is_ok());
+            block
+        });
+        match block {
+            Ok(block) => block.as_ptr(),
+            Err(()) => {
+                cprintln!("Out of memory for {} bytes aligned to {}: {}", layout.size(), layout.align(), stats());
+                ptr::null_mut()
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+        free(|cs| {
+            let state = &mut *self.0.borrow(cs).borrow_mut();
+            state.allocator.deallocate(NonNull::new_unchecked(block), layout);
+            state.stats.used = state.allocator.used();
+        });
+    }
+}
+
+/// Hand the memory to the allocator.  Call it once, early in `main`, before anything allocates.
+pub fn init() {
+    free(|cs| {
+        let state = &mut *HEAP.0.borrow(cs).borrow_mut();
+        // NOTE(unsafe) the only reference to the memory, which goes to the allocator for good.
+        state.allocator.init_from_slice(unsafe { &mut *addr_of_mut!(MEMORY) });
+        state.stats.size = state.allocator.size();
+    });
+}
+
+/// Usage so far.  Finding the largest free block takes a dozen trial allocations with
+/// interrupts off, so this is for reports, not for every allocation.
+pub fn stats() -> Stats {
+    free(|cs| {
+        let state = &mut *HEAP.0.borrow(cs).borrow_mut();
+        let mut stats = state.stats;
+        stats.largest_free = largest_free(&mut state.allocator);
+        stats
+    })
+}
+
+/// The largest allocation that would succeed, by bisection: if a size fits, so does any
+/// smaller one.
+fn largest_free(allocator: &mut Allocator) -> usize {
+    let (mut fits, mut too_big) = (0, allocator.free() + 1);
+    while too_big - fits > 1 {
+        let size = (fits + too_big) / 2;
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        match allocator.allocate_first_fit(layout) {
+            Ok(block) => {
+                // NOTE(unsafe) just allocated with this layout, and not used.
+                unsafe { allocator.deallocate(block, layout) };
+                fits = size;
+            }
+            Err(()) => too_big = size,
+        }
+    }
+    fits
+}
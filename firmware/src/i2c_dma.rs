@@ -0,0 +1,209 @@
+//! I2C1 transfers that run in the background: DMA1 channel 6 feeds the peripheral the bytes to
+//! write and channel 7 stores the ones it reads, so the CPU only steps in at the start of each
+//! phase and at the end (see `beginstm_shared::i2c`).
+//!
+//! `start` sets a transfer going and returns at once.  The I2C1 event and error interrupts
+//! carry it on, and at the end record the outcome, which wakes the main loop from its sleep;
+//! `take` collects it with the bytes read.
+//!
+//! The HAL's `I2c`, inside the sensor driver, still owns I2C1, for setting the sensor up and
+//! for scanning the bus, which block.  This module borrows the registers in between.  Whoever
+//! uses the driver must `wait` for a transfer here to end first; `Sensor` does.  Here only CR2
+//! and the DMA and interrupt enables in CR1 are written, the enables only while a transfer runs.
+
+use core::cell::RefCell;
+use core::ptr::addr_of_mut;
+
+use cortex_m::interrupt::{free, Mutex};
+
+use stm32f3xx_hal as hal;
+use hal::stm32::{self, interrupt};
+
+use beginstm_shared::i2c::{Error, Event, Step, Transfer};
+
+/// Most bytes a transfer writes and reads.
+pub const WRITE_LEN: usize = 4;
+pub const READ_LEN: usize = 16;
+
+// What the DMA channels read from and write to.  Only touched here while no transfer runs.
+static mut WRITE_BUFFER: [u8; WRITE_LEN] = [0; WRITE_LEN];
+static mut READ_BUFFER: [u8; READ_LEN] = [0; READ_LEN];
+
+struct State {
+    dma: stm32::DMA1,
+    transfer: Option<Transfer>,
+    /// How the last transfer ended, until `take` collects it.
+    result: Option<Result<(), Error>>,
+}
+
+impl State {
+    /// No transfer is running.
+    fn is_idle(&self) -> bool {
+        self.transfer.as_ref().is_none_or(Transfer::is_done)
+    }
+}
+
+static STATE: Mutex<RefCell<Option<State>>> = Mutex::new(RefCell::new(None));
+
+#[interrupt]
+// The end of a phase: TC, or STOPF with or without NACKF.  NACKIE stays off: the master sends
+// STOP after a NACK, so NACKF always comes with STOPF.
+fn I2C1_EV_EXTI23() {
+    let i2c = i2c();
+    let isr = i2c.isr.read();
+    let event = if isr.stopf().bit_is_set() {
+        Event::Stop { nack: isr.nackf().bit_is_set() }
+    } else if isr.tc().bit_is_set() {
+        // Cleared by the START of the next phase.
+        Event::TransferComplete
+    } else {
+        return;
+    };
+    i2c.icr.write(|w| w.stopcf().clear().nackcf().clear());
+    advance(event);
+}
+
+#[interrupt]
+// Bus error, lost arbitration or overrun.
+fn I2C1_ER() {
+    let i2c = i2c();
+    let event = if i2c.isr.read().arlo().bit_is_set() { Event::ArbitrationLost } else { Event::BusError };
+    i2c.icr.write(|w| w.berrcf().clear().arlocf().clear().ovrcf().clear());
+    advance(event);
+}
+
+fn advance(event: Event) {
+    free(|cs| {
+        if let Some(ref mut state) = *STATE.borrow(cs).borrow_mut() {
+            if let Some(ref mut transfer) = state.transfer {
+                let step = transfer.on_event(event);
+                if let Some(result) = perform(&state.dma, step) {
+                    state.result = Some(result);
+                }
+            }
+        }
+    });
+}
+
+fn i2c() -> &'static stm32::i2c1::RegisterBlock {
+    // NOTE(unsafe) see the module comment; the HAL doesn't use I2C1 while a transfer runs.
+    unsafe { &*stm32::I2C1::ptr() }
+}
+
+/// Take DMA1 for I2C1's channels.  Call it after the HAL has set up I2C1.  The caller unmasks
+/// the I2C1_EV_EXTI23 and I2C1_ER interrupts.
+pub fn init(dma: stm32::DMA1) {
+    // Power the DMA controller.  Only this module touches these bits.
+    unsafe { (*stm32::RCC::ptr()).ahbenr.modify(|_, w| w.dma1en().set_bit()) };
+
+    let i2c = i2c();
+    // NOTE(unsafe) the addresses of the data registers and of buffers that live for good.
+    unsafe {
+        dma.ch6.par.write(|w| w.bits(&i2c.txdr as *const _ as u32));
+        dma.ch6.mar.write(|w| w.bits(addr_of_mut!(WRITE_BUFFER) as u32));
+        dma.ch7.par.write(|w| w.bits(&i2c.rxdr as *const _ as u32));
+        dma.ch7.mar.write(|w| w.bits(addr_of_mut!(READ_BUFFER) as u32));
+    }
+    // Bytes both ends, stepping through the buffer.  Enabled for each phase.
+    dma.ch6.cr.write(|w| w.minc().set_bit().dir().from_memory());
+    dma.ch7.cr.write(|w| w.minc().set_bit().dir().from_peripheral());
+
+    free(|cs| {
+        STATE.borrow(cs).replace(Some(State { dma, transfer: None, result: None }));
+    });
+}
+
+/// Start writing `bytes` to the device at 7-bit `address` and then reading `read_len` bytes
+/// from it, and return.  Drops the outcome of the last transfer if it wasn't taken.
+pub fn start(address: u8, bytes: &[u8], read_len: usize) -> Result<(), Error> {
+    if bytes.len() > WRITE_LEN || read_len > READ_LEN {
+        return Err(Error::TooLong);
+    }
+    let mut transfer = Transfer::new(address, bytes.len(), read_len)?;
+    free(|cs| {
+        let mut state = STATE.borrow(cs).borrow_mut();
+        let state = match state.as_mut() {
+            Some(state) if state.is_idle() => state,
+            _ => return Err(Error::Busy),
+        };
+        // NOTE(unsafe) no transfer is running, so the DMA isn't reading the buffer.
+        unsafe { (&mut *addr_of_mut!(WRITE_BUFFER))[..bytes.len()].copy_from_slice(bytes) };
+        state.result = None;
+        i2c().cr1.modify(|_, w| {
+            w.txdmaen().set_bit().rxdmaen().set_bit();
+            w.tcie().set_bit().stopie().set_bit().errie().set_bit()
+        });
+        perform(&state.dma, transfer.start());
+        state.transfer = Some(transfer);
+        Ok(())
+    })
+}
+
+/// How the last transfer ended, once it has, with the bytes it read copied into the start of
+/// `into`.  None while it runs, and once the outcome has been taken.
+pub fn take(into: &mut [u8]) -> Option<Result<(), Error>> {
+    free(|cs| {
+        let result = STATE.borrow(cs).borrow_mut().as_mut()?.result.take()?;
+        if result.is_ok() {
+            let len = into.len().min(READ_LEN);
+            // NOTE(unsafe) the transfer is over, so the DMA isn't writing the buffer.
+            into[..len].copy_from_slice(unsafe { &(&*addr_of_mut!(READ_BUFFER))[..len] });
+        }
+        Some(result)
+    })
+}
+
+/// A transfer is running.
+pub fn is_busy() -> bool {
+    free(|cs| {
+        STATE.borrow(cs).borrow().as_ref().is_some_and(|state| !state.is_idle())
+    })
+}
+
+/// Wait for a running transfer to end, before using I2C1 through the HAL.  A transfer takes a
+/// millisecond or so at 100 kHz.  The outcome stays there for `take`.
+pub fn wait() {
+    while is_busy() {}
+}
+
+/// Do `step`, returning the outcome if the transfer is over.
+fn perform(dma: &stm32::DMA1, step: Step) -> Option<Result<(), Error>> {
+    let i2c = i2c();
+    match step {
+        Step::Write { cr2, len } => {
+            dma.ch6.cr.modify(|_, w| w.en().clear_bit());
+            dma.ch6.ndtr.write(|w| w.ndt().bits(len as u16));
+            if len > 0 {
+                dma.ch6.cr.modify(|_, w| w.en().set_bit());
+            }
+            // NOTE(unsafe) `Transfer` only sets the address, direction, count, START and AUTOEND.
+            i2c.cr2.write(|w| unsafe { w.bits(cr2) });
+            None
+        }
+        Step::Read { cr2, len } => {
+            dma.ch7.cr.modify(|_, w| w.en().clear_bit());
+            dma.ch7.ndtr.write(|w| w.ndt().bits(len as u16));
+            dma.ch7.cr.modify(|_, w| w.en().set_bit());
+            // NOTE(unsafe) as above.
+            i2c.cr2.write(|w| unsafe { w.bits(cr2) });
+            None
+        }
+        Step::Wait => None,
+        Step::Done(result) => {
+            dma.ch6.cr.modify(|_, w| w.en().clear_bit());
+            dma.ch7.cr.modify(|_, w| w.en().clear_bit());
+            i2c.cr1.modify(|_, w| {
+                w.txdmaen().clear_bit().rxdmaen().clear_bit();
+                w.tcie().clear_bit().stopie().clear_bit().errie().clear_bit()
+            });
+            if result.is_err() {
+                // Software reset, which puts the peripheral back to idle and frees the bus; the
+                // reference manual's sequence.
+                i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                while i2c.cr1.read().pe().bit_is_set() {}
+                i2c.cr1.modify(|_, w| w.pe().set_bit());
+            }
+            Some(result)
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! On/off control of the compass LEDs that the host tool is allowed to switch.
+//!
+//! LED n is PE(8 + n).  PE8 to PE11 are TIM1 PWM outputs and PE13 blinks on a software timer,
+//! so those five aren't available here.  The rest are dimmed by `softpwm`; on means full
+//! brightness.
+
+use stm32f3xx_hal::gpio::{Output, PushPull, PXx};
+
+use beginstm_shared::protocol::NackReason;
+
+use crate::softpwm;
+
+pub type Led = PXx<Output<PushPull>>;
+
+pub struct Leds {
+    free: u8,  // Bit n set if the host may switch LED n.
+    state: u8, // Bit n set if LED n is on.
+}
+
+impl Leds {
+    /// Bit n of `free` is set if LED n is the host's to switch.  They all start off.
+    pub fn new(free: u8) -> Self {
+        let mut leds = Leds { free, state: 0 };
+        for led in 0..8 {
+            leds.set(led, false).ok();
+        }
+        leds
+    }
+
+    pub fn set(&mut self, led: u8, on: bool) -> Result<(), NackReason> {
+        if led >= 8 {
+            return Err(NackReason::BadArgument);
+        }
+        if self.free & (1 << led) == 0 || !softpwm::set(led, if on { u8::MAX } else { 0 }) {
+            return Err(NackReason::Busy);
+        }
+        if on {
+            self.state |= 1 << led;
+        } else {
+            self.state &= !(1 << led);
+        }
+        Ok(())
+    }
+
+    /// Bit n set if LED n is on.
+    pub fn state(&self) -> u8 {
+        self.state
+    }
+}
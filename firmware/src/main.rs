@@ -0,0 +1,433 @@
+#![no_std]
+#![no_main]
+//#![deny(unsafe_code)]
+
+
+// Panics and hard faults are saved for the next boot and reset the chip; see panic.rs and
+// crash.rs.  Put a breakpoint on `rust_begin_unwind` or `HardFault` to catch them in the debugger.
+
+#[macro_use]
+mod console;
+mod animation;
+mod clocks;
+mod commands;
+mod crash;
+#[cfg(feature = "allocator")]
+mod heap;
+mod i2c_dma;
+mod leds;
+mod panic;
+mod power;
+mod regs;
+mod reset;
+mod rtc;
+mod sensor;
+mod softpwm;
+mod stack;
+mod telemetry;
+mod tim1;
+mod time;
+mod timers;
+mod usb_serial;
+mod watchdog;
+
+#[allow(unused_imports)]
+use core::cell::RefCell;
+use core::ops::DerefMut;
+
+use cortex_m_rt::entry;
+use cortex_m::{Peripherals, interrupt::free};
+//use cortex_m_semihosting::{hprintln};
+
+use stm32f3xx_hal as hal;
+
+use hal::pac;
+use hal::prelude::*;
+use hal::timer::Timer;
+use hal::stm32;
+use hal::usb::{Peripheral, UsbBus, UsbBusType};
+use stm32::{interrupt, Interrupt};
+use usb_device::bus::UsbBusAllocator;
+//use hal::pac::interrupt; // interrupt available from either pac or stm32.  Requires "rt" feature of the crate.
+
+use beginstm_shared::animation::Pattern;
+use beginstm_shared::isr;
+use beginstm_shared::protocol::{Message, Status};
+use beginstm_shared::spsc::Queue;
+use commands::CommandReader;
+use leds::Leds;
+use power::Power;
+use regs::Regs;
+use sensor::Sensor;
+use tim1::Tim1;
+use time::{Duration, Instant, Monotonic};
+use timers::Timers;
+use usb_serial::{UsbSerial, USB_SERIAL};
+use watchdog::Task;
+
+// Constants
+const DEFAULT_SAMPLE_RATE_HZ: u16 = 10;
+const STATUS_INTERVAL: Duration = Duration::from_secs(1); // How often to send a telemetry Status message.
+// The user button steps through these on the TIM1 LEDs.
+const LED_PATTERNS: [Pattern; 4] = [
+    Pattern::Breathe { period_ms: 4000 },
+    Pattern::Chase { period_ms: 1000 },
+    Pattern::Pulse { period_ms: 1000, width_ms: 400 },
+    Pattern::Fade { from: 255, to: 0, duration_ms: 3000 },
+];
+const SOUTH_LED: u8 = 5; // PE13
+const SOUTH_BLINK: Duration = Duration::from_secs(1); // Time on, then time off.
+const SOUTH_LEVEL: u8 = 48; // Brightness of the South LED's blink, to show off the dimming.
+const HOST_LEDS: u8 = 0b1101_0000; // PE12, PE14 and PE15 are for the host tool to switch.
+
+// What the software timers do when they expire; see the end of the main loop.
+#[derive(Clone, Copy)]
+enum Job {
+    BlinkSouth,
+    SendStatus,
+    ReportSoftPwm,
+}
+
+// Static variables.
+// When the user button was pressed, for the main loop; room for several presses between visits.
+static BUTTON_PRESSES: Queue<Instant, 8> = Queue::new();
+
+#[interrupt]
+// In the stm32f3-discovery board crate, this is abstracted to a button module or crate.
+fn EXTI0() {
+    // NOTE(unsafe) this handler is the only producer.
+    let mut presses = unsafe { BUTTON_PRESSES.producer_unchecked() };
+    // Clear the interrupt request so it won't fire again before another press.  PA0 has a
+    // low-pass filter, so don't need to debounce in software.
+    // NOTE(unsafe) only this handler writes PR1 once the button is set up.
+    isr::button(&Regs(unsafe { &*stm32::EXTI::ptr() }), &mut presses, Instant::now());
+}
+
+// The USB peripheral raises the low-priority interrupt for ordinary transfers and the
+// high-priority one for isochronous/double-buffered bulk.  Either way, just poll the device.
+#[interrupt]
+fn USB_LP_CAN_RX0() {
+    usb_poll();
+}
+
+#[interrupt]
+fn USB_HP_CAN_TX() {
+    usb_poll();
+}
+
+fn usb_poll() {
+    free(|cs| {
+        if let Some(ref mut serial) = USB_SERIAL.borrow(cs).borrow_mut().deref_mut() {
+            serial.poll();
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    // Console output goes to ITM and the USB serial port.  To print, use the cprintln!("...") or cprint!("...") macros.
+    // See the "itm.rs" example for plain ITM.
+    let mut p = Peripherals::take().unwrap();  // Cortex core peripherals
+    // A guard below the stack turns an overflow into a fault; see stack.rs.
+    stack::init(p.MPU);
+
+    // Get peripherals
+    let dp = pac::Peripherals::take().unwrap(); // Device peripherals
+    // Faults get handlers of their own and, like panics, are saved for the next boot.
+    crash::init();
+    #[cfg(feature = "allocator")]
+    heap::init();
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    // Configure pins on port A, where the user button and USB are.
+    let mut gpioa = dp.GPIOA.split(&mut rcc.ahb);
+    // For polling the button:
+    let user_button = gpioa.pa0.into_floating_input(&mut gpioa.moder, &mut gpioa.pupdr);
+
+    // Configure clocks and freeze them; see clocks.rs for the profiles.  The build picks one,
+    // but holding the user button through reset picks the 8 MHz HSI instead, without USB.
+    let profile = if user_button.is_high().unwrap() { clocks::Profile::Hsi8 } else { clocks::Profile::DEFAULT };
+    let clocks = profile.freeze(rcc.cfgr, &mut flash.acr);
+    // Match the ITM's SWO baud rate divider to the core clock.
+    clocks::init_swo(&mut p.TPIU, &dp.DBGMCU, &clocks);
+    // Start the microsecond clock behind every timestamp, timeout and delay.
+    time::init(dp.TIM2, &clocks);
+    // The cycle counter measures what the soft PWM costs.
+    p.DCB.enable_trace();
+    p.DWT.enable_cycle_counter();
+
+    // The calendar clock, which keeps its time through resets.  Most boards have no LSE crystal.
+    let rtc_source = rtc::init(dp.RTC, &dp.PWR, rtc::Source::Lsi);
+    // Low-power modes for when the main loop has nothing to do.
+    let mut power = Power::new(dp.PWR, p.SCB);
+    let from_standby = power.woke_from_standby();
+    let reset_cause = reset::cause(from_standby);
+    // From here on, a step that hangs, such as I2C on a stuck bus, resets the board.  Start-up
+    // checks in between steps; the other tasks are watched once it's over.
+    watchdog::start(dp.IWDG, p.SYST, &dp.DBGMCU, &clocks);
+    // The LED animation, soft PWM, software timers and USB all stop in Stop mode, so the
+    // main loop only ever sleeps.
+    power.hold(power::Mode::Stop);
+
+    // Timer 7 ticks the software timers, which pace everything periodic in the main loop.
+    let mut timers = Timers::new(dp.TIM7, &clocks);
+
+    // Besides polling the button as above, PA0 can be configured as an external interrupt source.
+    dp.EXTI.imr1.modify(|_, w| w.mr0().set_bit()); // External interrupt peripheral, interrupt mask register 1, bit zero for PA0.
+    dp.SYSCFG.exticr1.modify(|_, w| unsafe { w.exti0().bits(0x00)}); // Connect PA0 to the EXTI0 interrupt line.
+    dp.EXTI.rtsr1.modify(|_, w| w.tr0().set_bit());                  // Set the rising edge trigger for bit0 = PA0.
+    // External interrupt is enabled below before the infinite loop.
+
+    // Configure pins on port B, where the I2C peripheral is, then scan ports right before the infinite loop.
+    let mut gpiob = dp.GPIOB.split(&mut rcc.ahb);
+    let i2c_pins = (
+        gpiob.pb6.into_af4(&mut gpiob.moder, &mut gpiob.afrl), // SCL
+        gpiob.pb7.into_af4(&mut gpiob.moder, &mut gpiob.afrl), // SDA
+    );
+    let my_i2c = hal::i2c::I2c::new(dp.I2C1, i2c_pins, 100.khz(), clocks, &mut rcc.apb1);
+
+    // Configure pins on port E, where the board's LEDs are.
+    let mut gpioe = dp.GPIOE.split(&mut rcc.ahb);
+    // output for TIM1
+    // PE9 is the red "North" LED, PE8 the blue "North-West" one on the complementary output.
+    // PE11 is the green "East" LED, PE10 the orange "North-East" one on the complementary output.
+    let pe8 = gpioe.pe8.into_af2(&mut gpioe.moder, &mut gpioe.afrh);
+    let pe9 = gpioe.pe9.into_af2(&mut gpioe.moder, &mut gpioe.afrh);
+    let pe10 = gpioe.pe10.into_af2(&mut gpioe.moder, &mut gpioe.afrh);
+    let pe11 = gpioe.pe11.into_af2(&mut gpioe.moder, &mut gpioe.afrh);
+    // regular push-pull output PE13 is the red "South" LED.
+    let mut led = gpioe.pe13.into_push_pull_output(&mut gpioe.moder, &mut gpioe.otyper).downgrade().downgrade();
+    // The rest of the compass LEDs are dimmed in software, and apart from PE13 switched on and off by the host tool.
+    let pe12 = gpioe.pe12.into_push_pull_output(&mut gpioe.moder, &mut gpioe.otyper).downgrade().downgrade();
+    let pe14 = gpioe.pe14.into_push_pull_output(&mut gpioe.moder, &mut gpioe.otyper).downgrade().downgrade();
+    let pe15 = gpioe.pe15.into_push_pull_output(&mut gpioe.moder, &mut gpioe.otyper).downgrade().downgrade();
+
+    // Flash the LED manually to show how to use delays.  All of them go through the TIM2 clock
+    // in time.rs, rather than SysTick, a timer of their own or a count of CPU cycles.
+    led.set_high().unwrap();
+    time::delay(Duration::from_secs(1));
+    watchdog::check_in(Task::Startup);
+    led.set_low().unwrap();
+    // A delay can also be a deadline to check now and then, leaving the CPU free in between.
+    let deadline = Instant::now() + Duration::from_millis(100);
+    while Instant::now() < deadline {
+        cortex_m::asm::nop();
+    }
+    led.set_high().unwrap();
+    time::delay(Duration::from_secs(1));
+    watchdog::check_in(Task::Startup);
+
+    // Now that we have played around with the led, hand it to the software PWM with the other
+    // plain outputs; a software timer blinks it from there at a dimmed level.
+    // If just doing this and not manually toggling as above, "led" does not need to be defined as mutable.  
+    softpwm::init(
+        [
+            None, // PE8, TIM1 CH1N
+            None, // PE9, TIM1 CH1
+            None, // PE10, TIM1 CH2N
+            None, // PE11, TIM1 CH2
+            Some(pe12),
+            Some(led),
+            Some(pe14),
+            Some(pe15),
+        ],
+        dp.TIM16,
+        &clocks,
+    );
+    softpwm::set(SOUTH_LED, SOUTH_LEVEL);
+    let mut leds = Leds::new(HOST_LEDS);
+
+    // Configure TIM1, an advanced timer with complementary pins that drive the LEDs on the
+    // discovery board.  Our own driver (the HAL's can use only regular or complementary pins,
+    // not both) runs each channel's pair of LEDs in opposition, with a dead time between them
+    // as if they were the two halves of a bridge.
+    let mut tim1 = Tim1::new(
+        dp.TIM1,
+        tim1::Config {
+            frequency: 1.khz().into(),
+            dead_time_ns: 2000, // Long enough to see on a scope.
+            ..Default::default()
+        },
+        &clocks,
+    )
+    .unwrap();
+    let tim1_ch1 = tim1.ch1(Some(pe9), Some(pe8));
+    let tim1_ch2 = tim1.ch2(Some(pe11), Some(pe10));
+    // The animation engine owns the channels from here on and TIM4 paces its frames.
+    animation::init([tim1_ch1, tim1_ch2], Timer::tim4(dp.TIM4, animation::FRAME_HZ.hz(), clocks, &mut rcc.apb1));
+    // Start with the two LEDs doing different things; the button switches both to a shared pattern.
+    animation::set(animation::NORTH, Pattern::Breathe { period_ms: 4000 });
+    animation::set(animation::EAST, Pattern::Pulse { period_ms: 1000, width_ms: 400 });
+    let mut led_pattern = LED_PATTERNS.len() - 1;
+
+    // USB serial on PA11/PA12, if the clocks allow it.  Pull D+ low for a moment so the host
+    // notices a disconnect and re-enumerates after a reset or reflash; the board has a fixed
+    // 1.5k pull-up on D+.
+    if clocks.usbclk_valid() {
+        let mut usb_dp = gpioa.pa12.into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper);
+        usb_dp.set_low().unwrap();
+        time::delay(Duration::from_millis(10));
+        let usb = Peripheral {
+            usb: dp.USB,
+            pin_dm: gpioa.pa11.into_af14(&mut gpioa.moder, &mut gpioa.afrh),
+            pin_dp: usb_dp.into_af14(&mut gpioa.moder, &mut gpioa.afrh),
+        };
+        // The bus allocator must outlive the device that borrows it, so give it a static home.
+        let usb_bus: &'static UsbBusAllocator<UsbBusType> =
+            cortex_m::singleton!(: UsbBusAllocator<UsbBusType> = UsbBus::new(usb)).unwrap();
+        free(|cs| {
+            USB_SERIAL.borrow(cs).replace(Some(UsbSerial::new(usb_bus)));
+        });
+    }
+
+    cprintln!("Hello, big world!");
+    cprintln!("Clocks: {:?}, {} MHz", profile, clocks.sysclk().0 / 1_000_000);
+    cprint!("Reset: {:?} (", reset_cause);
+    for cause in reset::CAUSES.iter() {
+        cprint!(" {:?} {}", cause, reset::count(*cause));
+    }
+    cprintln!(" )");
+    match rtc::now() {
+        Some(now) => cprintln!("RTC ({:?}): {}", rtc_source, now),
+        None => cprintln!("RTC ({:?}) not set", rtc_source),
+    }
+    if let Some(record) = crash::take() {
+        cprintln!("{}", record);
+    }
+    if let Some(task) = watchdog::last_missed() {
+        cprintln!("Watchdog reset: {:?} missed its deadline", task);
+    }
+
+    // The accelerometer/magnetometer, read each time TIM6 fires.
+    let sample_timer = Timer::tim6(dp.TIM6, (DEFAULT_SAMPLE_RATE_HZ as u32).hz(), clocks, &mut rcc.apb1);
+    let mut sensor = Sensor::new(my_i2c, sample_timer, dp.DMA1, DEFAULT_SAMPLE_RATE_HZ);
+    watchdog::check_in(Task::Startup);
+    let mut commands = CommandReader::new();
+    let mut button_presses = BUTTON_PRESSES.consumer().unwrap();
+
+    // I2C address scan.
+    let devices = sensor.scan();
+    watchdog::check_in(Task::Startup);
+    for addr in 0x00_u8..0x80_u8 {
+        if devices.contains(addr) {
+            cprint!("{:02x} ", addr);
+        } else {
+            cprint!(".. ");
+        }
+        if addr % 0x10 == 0x0F {
+            cprintln!(" ");
+        }
+    }
+
+    timers.every(SOUTH_BLINK, Job::BlinkSouth).unwrap();
+    timers.every(STATUS_INTERVAL, Job::SendStatus).unwrap();
+    // Soft PWM figures now and a status interval later, to report what it costs, with the stack used.
+    timers.once(STATUS_INTERVAL, Job::ReportSoftPwm).unwrap();
+    let pwm_stats = softpwm::stats();
+
+    // From here on, each task that stops checking in resets the board.
+    watchdog::started();
+
+    // Enable interrupts.
+    unsafe {
+        stm32::NVIC::unmask(Interrupt::TIM2);
+        stm32::NVIC::unmask(Interrupt::TIM7);
+        stm32::NVIC::unmask(Interrupt::TIM4);
+        stm32::NVIC::unmask(Interrupt::TIM1_UP_TIM16);
+        stm32::NVIC::unmask(Interrupt::EXTI0);
+        stm32::NVIC::unmask(Interrupt::RTC_WKUP);
+        stm32::NVIC::unmask(Interrupt::RTCALARM);
+        stm32::NVIC::unmask(Interrupt::TIM6_DACUNDER);
+        stm32::NVIC::unmask(Interrupt::I2C1_EV_EXTI23);
+        stm32::NVIC::unmask(Interrupt::I2C1_ER);
+        stm32::NVIC::unmask(Interrupt::USB_LP_CAN_RX0);
+        stm32::NVIC::unmask(Interrupt::USB_HP_CAN_TX);
+    }
+
+    // Loop, flashing the South LED manually if first line is uncommented and led is not moved to the soft PWM, or from a software timer otherwise.
+    loop {
+//        led.toggle().unwrap();
+        // Check button once per interrupt.  Note that is_high() returns a result.
+        // match user_button.is_high() {
+        //     Ok(true) => {
+        //         cprintln!("Button pressed");
+        //     }
+        //     Ok(false) => {
+        //         ()
+        //     }
+        //     Err(_) => {
+        //         cprintln!("Error from button call");
+        //     }
+        // }
+        // Alternate form if we don't want to print anything about the error.
+        // if let Ok(true) = user_button.is_high() {
+        //     cprintln!("Button pressed");
+        // }
+
+        // Every press since the last time round, each stepping the pattern on.
+        while let Some(pressed_at) = button_presses.dequeue() {
+            telemetry::send(Message::Button { pressed: true });
+            if let Some(now) = rtc::now() {
+                cprintln!("Button pressed at {}, seen {} us later", now, (Instant::now() - pressed_at).as_micros());
+            }
+            led_pattern = (led_pattern + 1) % LED_PATTERNS.len();
+            animation::set_all(LED_PATTERNS[led_pattern]);
+        }
+
+        if rtc::alarm_fired() {
+            cprintln!("RTC alarm");
+        }
+
+        // Carry out anything the host tool asked for.
+        commands.poll(&mut sensor, &mut leds);
+        watchdog::check_in(Task::Comms);
+
+        // Read the sensors in the background and stream the raw samples as telemetry.
+        if sensor.sample_due() {
+            sensor.start_reading();
+        }
+        if let Some(reading) = sensor.reading() {
+            if let Some(accel) = reading.accel {
+                telemetry::send(Message::Accel(accel));
+            }
+            if let Some(mag) = reading.mag {
+                telemetry::send(Message::Mag(mag));
+            }
+            watchdog::check_in(Task::Sensor);
+        }
+
+        // Whatever the software timers say is due.
+        while let Some(job) = timers.poll() {
+            match job {
+                Job::BlinkSouth => {
+                    let on = softpwm::level(SOUTH_LED).unwrap_or(0) != 0;
+                    softpwm::set(SOUTH_LED, if on { 0 } else { SOUTH_LEVEL });
+                }
+                Job::SendStatus => {
+                    let dropped = free(|cs| USB_SERIAL.borrow(cs).borrow().as_ref().map_or(0, |s| s.dropped()));
+                    let time = rtc::now().map_or(0, |now| now.to_unix());
+                    telemetry::send(Message::Status(Status { dropped, sensor_errors: sensor.errors(), time }));
+                }
+                Job::ReportSoftPwm => {
+                    let end = softpwm::stats();
+                    let cycles = end.cycles.wrapping_sub(pwm_stats.cycles);
+                    let permille = cycles as u64 * 1000 / (clocks.sysclk().0 as u64 * STATUS_INTERVAL.as_millis() as u64 / 1000);
+                    cprintln!(
+                        "soft PWM: {} interrupts, {} cycles in {} ms, {}.{}% CPU",
+                        end.interrupts.wrapping_sub(pwm_stats.interrupts),
+                        cycles,
+                        STATUS_INTERVAL.as_millis(),
+                        permille / 10,
+                        permille % 10
+                    );
+                    cprintln!("Stack: {} of {} bytes used at most", stack::high_water(), stack::size());
+                    #[cfg(feature = "allocator")]
+                    cprintln!("Heap: {}", heap::stats());
+                }
+            }
+        }
+
+        power.idle(None);     // Wait for interrupt, in the deepest mode allowed.
+    }
+}
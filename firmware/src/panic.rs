@@ -0,0 +1,83 @@
+//! The firmware's own panic handler, in place of a panic-handler crate.
+//!
+//! It logs the location and message to the console, saves them for the next boot to report
+//! (see crash.rs) and resets the chip.  Two features change what happens after the record is
+//! saved:
+//!
+//! - `panic-blink` flashes all eight compass LEDs, for a couple of seconds before the reset or
+//!   for as long as it stays halted.
+//! - `halt-on-panic` stays in the handler instead of resetting, for a debugger to look at.
+//!   It keeps feeding the watchdog, which would otherwise reset the chip within a second.
+//!
+//! Interrupts are off from the start, so of the console only ITM gets the message; the USB
+//! packets are never sent.  A panic inside the handler, for instance from the console, skips
+//! straight to the end.
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::peripheral::SCB;
+
+use stm32f3xx_hal as hal;
+use hal::stm32;
+
+use beginstm_shared::crash::{CrashRecord, Kind};
+use crate::crash;
+use crate::time::{self, Duration};
+use crate::watchdog;
+
+const BLINK: Duration = Duration::from_millis(100);
+/// Flashes before the reset, without `halt-on-panic`.
+const BLINKS: u32 = 10;
+/// The compass LEDs, PE8 to PE15.
+const LEDS: u32 = 0xff00;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        let mut record = CrashRecord::new(Kind::Panic);
+        if let Some(location) = info.location() {
+            write!(record, "{}:{}:{}: ", location.file(), location.line(), location.column()).ok();
+        }
+        write!(record, "{}", info.message()).ok();
+        crash::save(record);
+        cprintln!("Panic at {}", record.message());
+
+        if cfg!(feature = "panic-blink") && time::is_running() {
+            leds_to_outputs();
+            let blinks = if cfg!(feature = "halt-on-panic") { u32::MAX } else { BLINKS };
+            for _ in 0..blinks {
+                toggle_leds();
+                watchdog::feed();
+                time::delay(BLINK);
+            }
+        }
+    }
+
+    if cfg!(feature = "halt-on-panic") {
+        loop {
+            watchdog::feed();
+        }
+    }
+    SCB::sys_reset()
+}
+
+/// Take the LED pins from their timers and software PWM, all off.
+fn leds_to_outputs() {
+    // NOTE(unsafe) nothing else runs any more.  Clock port E in case the panic came first.
+    unsafe {
+        (*stm32::RCC::ptr()).ahbenr.modify(|_, w| w.iopeen().set_bit());
+        let gpioe = &*stm32::GPIOE::ptr();
+        gpioe.bsrr.write(|w| w.bits(LEDS << 16));
+        gpioe.moder.modify(|r, w| w.bits((r.bits() & 0x0000_ffff) | 0x5555_0000));
+    }
+}
+
+fn toggle_leds() {
+    // NOTE(unsafe) as above.
+    unsafe { (*stm32::GPIOE::ptr()).odr.modify(|r, w| w.bits(r.bits() ^ LEDS)) };
+}
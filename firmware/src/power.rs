@@ -0,0 +1,245 @@
+//! Low-power modes, and a policy that picks the deepest one the application allows.
+//!
+//! From lightest to deepest:
+//!
+//! - Sleep: the core stops until an interrupt; everything else runs.  With sleep-on-exit, the
+//!   core goes back to sleep at the end of each interrupt handler instead of returning to the
+//!   main loop, for applications that do all their work in interrupts.
+//! - Stop: every clock in the 1.8 V domain stops, so the timers, PWM and USB stop too, but RAM
+//!   and registers are kept.  Any EXTI line wakes it: the user button (EXTI0) or the RTC
+//!   wake-up timer (EXTI20).  The chip wakes on the HSI, so `idle` puts back the HSE and PLL
+//!   that `clocks::Profile::freeze` set up.  TIM2 stops with the rest, so `Instant::now()` and
+//!   the software timers don't count the time spent in Stop.
+//! - Standby: everything but the backup domain is off.  Only a rising edge on WKUP1 (PA0, the
+//!   user button), the RTC or a reset wakes it, and waking is a reset.
+//!
+//! The RTC wake-up timer counts the RTC clock (see `rtc.rs`), so on the LSI, wake-up times are
+//! only good to a few percent.
+//!
+//! Code that needs the clocks running calls `hold(Mode::Stop)`, and `release` when it is done;
+//! `idle` then never goes below Sleep.  Holding Standby keeps the chip out of Standby only.  A
+//! debugger loses the chip in Stop and Standby unless the DBG_STOP and DBG_STANDBY bits are set
+//! in DBGMCU_CR.
+
+use cortex_m::peripheral::SCB;
+
+use stm32f3xx_hal as hal;
+use hal::stm32::{self, interrupt};
+
+use crate::rtc;
+use crate::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Mode {
+    Sleep,
+    Stop,
+    Standby,
+}
+
+#[interrupt]
+// The RTC wake-up timer went off.  Waking was the point, so just clear the flags.
+fn RTC_WKUP() {
+    clear_wakeup_flags();
+}
+
+fn clear_wakeup_flags() {
+    // NOTE(unsafe) only this module touches the wake-up flag and EXTI line 20.
+    unsafe {
+        (*stm32::RTC::ptr()).isr.modify(|_, w| w.wutf().clear_bit());
+        (*stm32::EXTI::ptr()).pr1.write(|w| w.pr20().set_bit());
+    }
+}
+
+/// Let the main loop run again after an interrupt when sleep-on-exit is on.  For interrupt
+/// handlers, which don't have the `Power`.
+#[allow(dead_code)]
+pub fn wake_main() {
+    // NOTE(unsafe) SLEEPONEXIT is only changed here and by `Power`, each with a single write.
+    unsafe { (*SCB::ptr()).scr.modify(|scr| scr & !SCR_SLEEPONEXIT) };
+}
+
+const SCR_SLEEPONEXIT: u32 = 1 << 1;
+
+pub struct Power {
+    pwr: stm32::PWR,
+    scb: SCB,
+    /// How many holds there are on Stop and on Standby.
+    holds: [u8; 2],
+}
+
+impl Power {
+    /// Take charge of the power modes.  `rtc::init` must have run, for the wake-up timer.
+    /// The caller unmasks the RTC_WKUP interrupt.
+    pub fn new(pwr: stm32::PWR, scb: SCB) -> Self {
+        // The wake-up timer reaches the NVIC and the Stop-mode wake-up logic through EXTI20.
+        // NOTE(unsafe) only this module touches EXTI line 20.
+        unsafe {
+            let exti = &*stm32::EXTI::ptr();
+            exti.imr1.modify(|_, w| w.mr20().set_bit());
+            exti.rtsr1.modify(|_, w| w.tr20().set_bit());
+        }
+
+        Power { pwr, scb, holds: [0; 2] }
+    }
+
+    /// True if the chip was reset by waking from Standby.  Clears the record.
+    pub fn woke_from_standby(&mut self) -> bool {
+        let standby = self.pwr.csr.read().sbf().bit_is_set();
+        self.pwr.cr.modify(|_, w| w.csbf().set_bit());
+        standby
+    }
+
+    /// Keep `idle` out of `mode` and anything deeper, until the matching `release`.
+    /// Sleep can't be held.
+    pub fn hold(&mut self, mode: Mode) {
+        if let Some(holds) = self.holds_mut(mode) {
+            *holds = holds.saturating_add(1);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn release(&mut self, mode: Mode) {
+        if let Some(holds) = self.holds_mut(mode) {
+            *holds = holds.saturating_sub(1);
+        }
+    }
+
+    fn holds_mut(&mut self, mode: Mode) -> Option<&mut u8> {
+        match mode {
+            Mode::Sleep => None,
+            Mode::Stop => Some(&mut self.holds[0]),
+            Mode::Standby => Some(&mut self.holds[1]),
+        }
+    }
+
+    /// The deepest mode nothing holds.
+    pub fn deepest_allowed(&self) -> Mode {
+        if self.holds[0] > 0 {
+            Mode::Sleep
+        } else if self.holds[1] > 0 {
+            Mode::Stop
+        } else {
+            Mode::Standby
+        }
+    }
+
+    /// Wait in the deepest mode allowed until an interrupt, or until `wake_after` if given,
+    /// which only applies to Stop and Standby and is at most 26 seconds (32 on the LSE).
+    /// Returns the mode it was in, unless that was Standby, which ends in a reset.
+    pub fn idle(&mut self, wake_after: Option<Duration>) -> Mode {
+        let mode = self.deepest_allowed();
+        match mode {
+            Mode::Sleep => self.sleep(),
+            Mode::Stop => self.stop(wake_after),
+            Mode::Standby => self.standby(wake_after),
+        }
+        mode
+    }
+
+    /// Sleep until an interrupt.
+    pub fn sleep(&mut self) {
+        self.scb.clear_sleepdeep();
+        cortex_m::asm::wfi();
+    }
+
+    /// Turn sleep-on-exit on or off.  While it's on, the main loop only gets to run again after
+    /// an interrupt calls `wake_main`.
+    #[allow(dead_code)]
+    pub fn sleep_on_exit(&mut self, on: bool) {
+        if on {
+            self.scb.set_sleeponexit();
+        } else {
+            self.scb.clear_sleeponexit();
+        }
+    }
+
+    /// Stop until an EXTI interrupt or `wake_after`, then restore the clocks.
+    pub fn stop(&mut self, wake_after: Option<Duration>) {
+        let saved = SavedClocks::save();
+        self.arm_wakeup(wake_after);
+        // Regulator in low-power mode, Stop rather than Standby.
+        self.pwr.cr.modify(|_, w| w.pdds().clear_bit().lpds().set_bit());
+        self.scb.set_sleepdeep();
+        // With interrupts masked, the one that wakes it stays pending until the clocks are back,
+        // instead of running on the HSI.
+        let scb = &mut self.scb;
+        cortex_m::interrupt::free(|_| {
+            cortex_m::asm::dsb();
+            cortex_m::asm::wfi();
+            scb.clear_sleepdeep();
+            saved.restore();
+            rtc::resync();
+        });
+        self.disarm_wakeup();
+    }
+
+    /// Go into Standby until the user button, `wake_after` or a reset.
+    pub fn standby(&mut self, wake_after: Option<Duration>) -> ! {
+        self.arm_wakeup(wake_after);
+        self.pwr.csr.modify(|_, w| w.ewup1().set_bit());
+        // A wake-up flag left set would wake it straight away.
+        self.pwr.cr.modify(|_, w| w.cwuf().set_bit().pdds().set_bit());
+        self.scb.set_sleepdeep();
+        loop {
+            cortex_m::asm::dsb();
+            cortex_m::asm::wfi();
+        }
+    }
+
+    fn arm_wakeup(&mut self, after: Option<Duration>) {
+        let after = match after {
+            Some(after) => after,
+            None => return,
+        };
+        // The wake-up timer counts the RTC clock divided by 16.
+        let hz = rtc::clock_hz() / 16;
+        let ticks = (after.as_micros() as u64 * hz as u64 / 1_000_000).clamp(1, 0x1_0000) as u16;
+        rtc::unlocked(|rtc| {
+            rtc.cr.modify(|_, w| w.wute().clear_bit());
+            while rtc.isr.read().wutwf().bit_is_clear() {}
+            rtc.wutr.write(|w| w.wut().bits(ticks.wrapping_sub(1)));
+            rtc.cr.modify(|_, w| w.wucksel().div16().wutie().set_bit().wute().set_bit());
+        });
+        clear_wakeup_flags();
+    }
+
+    fn disarm_wakeup(&mut self) {
+        rtc::unlocked(|rtc| rtc.cr.modify(|_, w| w.wute().clear_bit().wutie().clear_bit()));
+        clear_wakeup_flags();
+    }
+}
+
+/// What Stop turns off in the clock tree.  The PLL settings, prescalers and flash wait states
+/// survive, so turning the oscillators back on and switching to the old source is enough.
+struct SavedClocks {
+    hse: bool,
+    pll: bool,
+    source: u8,
+}
+
+impl SavedClocks {
+    fn save() -> Self {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let cr = rcc.cr.read();
+        SavedClocks {
+            hse: cr.hseon().bit_is_set(),
+            pll: cr.pllon().bit_is_set(),
+            source: rcc.cfgr.read().sw().bits(),
+        }
+    }
+
+    fn restore(&self) {
+        // Only this module and the HAL's freeze, long since done, touch these bits.
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        if self.hse {
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
+            while rcc.cr.read().hserdy().bit_is_clear() {}
+        }
+        if self.pll {
+            rcc.cr.modify(|_, w| w.pllon().set_bit());
+            while rcc.cr.read().pllrdy().bit_is_clear() {}
+        }
+        rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(self.source) });
+        while rcc.cfgr.read().sws().bits() != self.source {}
+    }
+}
@@ -0,0 +1,70 @@
+//! The shared crate's register traits on the PAC's register blocks, for the handler bodies in
+//! `beginstm_shared::isr`.  Neither the traits nor the blocks are this crate's, so the blocks
+//! go in a `Regs` first: `&Regs(&*stm32::X::ptr())`, or `&Regs(&*owned)`.
+
+use stm32f3xx_hal::stm32::{exti, gpioc, tim16, tim6};
+
+use beginstm_shared::regs::{Exti, Gpio, Timer};
+
+pub struct Regs<'a, T>(pub &'a T);
+
+impl Exti for Regs<'_, exti::RegisterBlock> {
+    fn pending(&self) -> u32 {
+        self.0.pr1.read().bits()
+    }
+
+    fn clear_pending(&self, lines: u32) {
+        // NOTE(unsafe) writing 0 to a bit of PR1 does nothing, so any value is safe.
+        self.0.pr1.write(|w| unsafe { w.bits(lines) });
+    }
+}
+
+/// TIM6 and TIM7.
+impl Timer for Regs<'_, tim6::RegisterBlock> {
+    fn update_pending(&self) -> bool {
+        self.0.sr.read().uif().bit_is_set()
+    }
+
+    fn clear_update(&self) {
+        self.0.sr.modify(|_, w| w.uif().clear_bit());
+    }
+
+    fn set_reload(&self, reload: u16) {
+        self.0.arr.write(|w| w.arr().bits(reload));
+    }
+
+    fn force_update(&self) {
+        self.0.egr.write(|w| w.ug().set_bit());
+    }
+}
+
+/// TIM16.
+impl Timer for Regs<'_, tim16::RegisterBlock> {
+    fn update_pending(&self) -> bool {
+        self.0.sr.read().uif().bit_is_set()
+    }
+
+    fn clear_update(&self) {
+        self.0.sr.modify(|_, w| w.uif().clear());
+    }
+
+    fn set_reload(&self, reload: u16) {
+        self.0.arr.write(|w| unsafe { w.arr().bits(reload) });
+    }
+
+    fn force_update(&self) {
+        self.0.egr.write(|w| w.ug().set_bit());
+    }
+}
+
+/// GPIOC to GPIOE.
+impl Gpio for Regs<'_, gpioc::RegisterBlock> {
+    fn output(&self) -> u16 {
+        self.0.odr.read().bits() as u16
+    }
+
+    fn set_reset(&self, bits: u32) {
+        // NOTE(unsafe) BSRR writes are atomic and only touch the pins named in them.
+        self.0.bsrr.write(|w| unsafe { w.bits(bits) });
+    }
+}
@@ -0,0 +1,83 @@
+//! Why the chip last reset, and how often each reason has come up.
+//!
+//! The RCC keeps a flag for each kind of reset in RCC_CSR until software clears them, so
+//! several can be set at once: the NRST pin flag goes with nearly every reset, since the chip
+//! drives the pin itself, and a power-on sets it too.  `cause` picks the most specific one and
+//! clears them all for next time.  Waking from Standby leaves no flag here, only in PWR_CSR,
+//! which `Power::woke_from_standby` reads.
+//!
+//! The counts live in RTC backup registers 1 to 8, so they survive everything but losing the
+//! backup domain's power.  On the Discovery board that happens with every power cycle, so the
+//! power-on count only ever reaches 1.
+
+use stm32f3xx_hal as hal;
+use hal::stm32;
+
+use crate::rtc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetCause {
+    PowerOn = 0,
+    /// The reset button, or a debugger pulling NRST.
+    Pin = 1,
+    /// The independent watchdog; see `watchdog::last_missed` for which task.
+    Watchdog = 2,
+    WindowWatchdog = 3,
+    /// `SCB::sys_reset`, as after a panic or a flash from the debugger.
+    Software = 4,
+    /// Entering Stop or Standby with the option bytes set to reset instead.
+    LowPower = 5,
+    /// Loading new option bytes.
+    OptionBytes = 6,
+    /// A wake-up from Standby: the button, the RTC or the WKUP pin.
+    StandbyWake = 7,
+}
+
+pub const CAUSES: [ResetCause; 8] = [
+    ResetCause::PowerOn,
+    ResetCause::Pin,
+    ResetCause::Watchdog,
+    ResetCause::WindowWatchdog,
+    ResetCause::Software,
+    ResetCause::LowPower,
+    ResetCause::OptionBytes,
+    ResetCause::StandbyWake,
+];
+
+/// The first of the backup registers that hold the counts, in `CAUSES` order.
+const FIRST_COUNTER: usize = 1;
+
+/// Read and clear the reset flags, count the cause and return it.  `from_standby` is what
+/// `Power::woke_from_standby` said.  `rtc::init` must have run, for the counters.  Call it once.
+pub fn cause(from_standby: bool) -> ResetCause {
+    // Only this module touches the reset flags.
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let csr = rcc.csr.read();
+    let cause = if from_standby {
+        ResetCause::StandbyWake
+    } else if csr.iwdgrstf().bit_is_set() {
+        ResetCause::Watchdog
+    } else if csr.wwdgrstf().bit_is_set() {
+        ResetCause::WindowWatchdog
+    } else if csr.lpwrrstf().bit_is_set() {
+        ResetCause::LowPower
+    } else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    } else if csr.oblrstf().bit_is_set() {
+        ResetCause::OptionBytes
+    } else if csr.porrstf().bit_is_set() {
+        ResetCause::PowerOn
+    } else {
+        ResetCause::Pin
+    };
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+    let register = FIRST_COUNTER + cause as usize;
+    rtc::set_backup(register, rtc::backup(register).wrapping_add(1));
+    cause
+}
+
+/// How many times `cause` has reset the chip, this one included.
+pub fn count(cause: ResetCause) -> u32 {
+    rtc::backup(FIRST_COUNTER + cause as usize)
+}
@@ -0,0 +1,277 @@
+//! Real-time clock: the date and time of day, and an alarm.
+//!
+//! The RTC lives in the backup domain, so once running it keeps counting through resets and
+//! the low-power modes; on the Discovery board VBAT is tied to VDD, so a power cycle stops it.
+//! It counts from the 32.768 kHz LSE crystal if one is fitted (X3, missing on most boards) or
+//! from the internal LSI, which is only good to a few percent: it will gain or lose minutes a
+//! day.  `init` leaves a running RTC, and the time it holds, alone.
+//!
+//! The calendar is in BCD registers that hold years 2000 to 2099; `beginstm_shared::calendar`
+//! converts to and from `DateTime` and Unix time.  The RTC's registers are write-protected, and
+//! every change goes through `unlocked`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use stm32f3xx_hal as hal;
+use hal::stm32::{self, interrupt};
+
+use beginstm_shared::calendar::{from_bcd, to_bcd, DateTime};
+use crate::time::{Duration, Instant, Monotonic};
+
+/// How long to give the LSE to start before falling back to the LSI.  The crystal's start-up
+/// time is typically 2 s.
+const LSE_TIMEOUT: Duration = Duration::from_secs(3);
+const LSE_HZ: u32 = 32_768;
+/// Nominal LSI frequency.  The real one is anywhere from 30 to 50 kHz.
+const LSI_HZ: u32 = 40_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Lse,
+    Lsi,
+}
+
+impl Source {
+    pub fn hz(self) -> u32 {
+        match self {
+            Source::Lse => LSE_HZ,
+            Source::Lsi => LSI_HZ,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Not a date and time, or outside 2000 to 2099.
+    Invalid,
+}
+
+/// When alarm A goes off: at a time of day, every day or on one day of the month.
+#[derive(Clone, Copy, Debug)]
+pub struct Alarm {
+    /// Day of the month, or None for every day.
+    pub day: Option<u8>,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+static ALARM: AtomicBool = AtomicBool::new(false);
+
+#[interrupt]
+// Alarm A went off.
+fn RTCALARM() {
+    // NOTE(unsafe) only this module touches the alarm flag and EXTI line 17.
+    unsafe {
+        (*stm32::RTC::ptr()).isr.modify(|_, w| w.alraf().clear_bit());
+        (*stm32::EXTI::ptr()).pr1.write(|w| w.pr17().set_bit());
+    }
+    ALARM.store(true, Ordering::Relaxed);
+}
+
+fn rtc() -> &'static stm32::rtc::RegisterBlock {
+    // NOTE(unsafe) the registers are only changed through `unlocked`, with interrupts masked.
+    unsafe { &*stm32::RTC::ptr() }
+}
+
+/// Start the RTC from `source`, unless it's running already, and unlock the backup domain.
+/// Returns the source it runs from, which is the LSI if the LSE doesn't start, or whatever the
+/// running RTC already had.  `time::init` must have run.  The caller unmasks RTCALARM.
+pub fn init(_rtc: stm32::RTC, pwr: &stm32::PWR, source: Source) -> Source {
+    // Only this module touches these RCC bits.
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+    pwr.cr.modify(|_, w| w.dbp().set_bit()); // Allow writes to the backup domain.
+
+    // The alarm reaches the NVIC through EXTI17.
+    // NOTE(unsafe) only this module touches EXTI line 17.
+    unsafe {
+        let exti = &*stm32::EXTI::ptr();
+        exti.imr1.modify(|_, w| w.mr17().set_bit());
+        exti.rtsr1.modify(|_, w| w.tr17().set_bit());
+    }
+
+    if rcc.bdcr.read().rtcen().bit_is_set() {
+        let running = self::source().unwrap_or(Source::Lsi);
+        // LSION is in RCC_CSR, which every reset clears, so an LSI calendar stops until it's
+        // turned back on.
+        if self::source() == Some(Source::Lsi) {
+            start_lsi(rcc);
+        }
+        return running;
+    }
+
+    let mut source = source;
+    if source == Source::Lse {
+        rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+        let start = Instant::now();
+        while rcc.bdcr.read().lserdy().bit_is_clear() {
+            if Instant::now() - start > LSE_TIMEOUT {
+                rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+                source = Source::Lsi;
+                break;
+            }
+        }
+    }
+    if source == Source::Lsi {
+        start_lsi(rcc);
+        rcc.bdcr.modify(|_, w| w.rtcsel().lsi().rtcen().set_bit());
+    } else {
+        rcc.bdcr.modify(|_, w| w.rtcsel().lse().rtcen().set_bit());
+    }
+
+    // Divide down to 1 Hz, by 128 then by the rest.  The LSI's 312.5 is rounded down.
+    let sync = (source.hz() / 128 - 1) as u16;
+    unlocked(|rtc| {
+        enter_init(rtc);
+        rtc.prer.write(|w| w.prediv_a().bits(127).prediv_s().bits(sync));
+        exit_init(rtc);
+    });
+    source
+}
+
+fn start_lsi(rcc: &stm32::rcc::RegisterBlock) {
+    rcc.csr.modify(|_, w| w.lsion().set_bit());
+    while rcc.csr.read().lsirdy().bit_is_clear() {}
+}
+
+/// What the RTC counts, or None if it isn't running.
+pub fn source() -> Option<Source> {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let bdcr = rcc.bdcr.read();
+    match (bdcr.rtcen().bit_is_set(), bdcr.rtcsel().bits()) {
+        (true, 0b01) => Some(Source::Lse),
+        (true, 0b10) => Some(Source::Lsi),
+        _ => None,
+    }
+}
+
+/// Frequency of the RTC clock, nominally, for the wake-up timer.
+pub fn clock_hz() -> u32 {
+    source().map_or(LSI_HZ, Source::hz)
+}
+
+/// True once the date and time have been set, since the backup domain was last powered.
+pub fn is_set() -> bool {
+    rtc().isr.read().inits().bit_is_set()
+}
+
+/// The date and time, or None if they haven't been set.
+pub fn now() -> Option<DateTime> {
+    if !is_set() {
+        return None;
+    }
+    // Reading TR freezes the shadow registers until DR is read, so the two go together.
+    let tr = rtc().tr.read().bits();
+    let dr = rtc().dr.read().bits();
+    Some(DateTime {
+        year: 2000 + from_bcd((dr >> 16) as u8) as u16,
+        month: from_bcd((dr >> 8) as u8 & 0x1f),
+        day: from_bcd(dr as u8 & 0x3f),
+        hour: from_bcd((tr >> 16) as u8 & 0x3f),
+        minute: from_bcd((tr >> 8) as u8 & 0x7f),
+        second: from_bcd(tr as u8 & 0x7f),
+    })
+}
+
+/// Set the date and time.  The seconds start counting from the start of a second.
+pub fn set(date: &DateTime) -> Result<(), Error> {
+    if !date.is_valid() {
+        return Err(Error::Invalid);
+    }
+    let tr = ((to_bcd(date.hour) as u32) << 16) | ((to_bcd(date.minute) as u32) << 8) | to_bcd(date.second) as u32;
+    let dr = ((to_bcd((date.year - 2000) as u8) as u32) << 16)
+        | ((date.weekday() as u32) << 13)
+        | ((to_bcd(date.month) as u32) << 8)
+        | to_bcd(date.day) as u32;
+    unlocked(|rtc| {
+        enter_init(rtc);
+        rtc.tr.write(|w| unsafe { w.bits(tr) });
+        rtc.dr.write(|w| unsafe { w.bits(dr) });
+        exit_init(rtc);
+    });
+    resync();
+    Ok(())
+}
+
+/// Set alarm A, replacing any earlier one.
+#[allow(dead_code)]
+pub fn set_alarm(alarm: Alarm) -> Result<(), Error> {
+    if alarm.hour > 23 || alarm.minute > 59 || alarm.second > 59 || alarm.day.is_some_and(|d| !(1..=31).contains(&d)) {
+        return Err(Error::Invalid);
+    }
+    // MSK4 set ignores the date; WDSEL is left clear, so the date is a day of the month.
+    let date = match alarm.day {
+        Some(day) => (to_bcd(day) as u32) << 24,
+        None => 1 << 31,
+    };
+    let alrmar = date
+        | ((to_bcd(alarm.hour) as u32) << 16)
+        | ((to_bcd(alarm.minute) as u32) << 8)
+        | to_bcd(alarm.second) as u32;
+    unlocked(|rtc| {
+        rtc.cr.modify(|_, w| w.alrae().clear_bit().alraie().clear_bit());
+        while rtc.isr.read().alrawf().bit_is_clear() {}
+        rtc.alrmar.write(|w| unsafe { w.bits(alrmar) });
+        rtc.isr.modify(|_, w| w.alraf().clear_bit());
+        rtc.cr.modify(|_, w| w.alrae().set_bit().alraie().set_bit());
+    });
+    ALARM.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+#[allow(dead_code)]
+pub fn cancel_alarm() {
+    unlocked(|rtc| rtc.cr.modify(|_, w| w.alrae().clear_bit().alraie().clear_bit()));
+}
+
+/// True if the alarm has gone off since the last call.
+pub fn alarm_fired() -> bool {
+    ALARM.swap(false, Ordering::Relaxed)
+}
+
+/// One of the 32-bit backup registers, 0 to 15, which keep their value through resets and
+/// Standby as long as the backup domain has power.  Register 0 is the watchdog's and 1 to 8
+/// are `reset`'s.
+pub fn backup(register: usize) -> u32 {
+    rtc().bkpr[register].read().bits()
+}
+
+pub fn set_backup(register: usize, value: u32) {
+    // The backup registers aren't write-protected, only the backup domain, which `init` opened.
+    rtc().bkpr[register].write(|w| unsafe { w.bits(value) });
+}
+
+/// Wait for the shadow registers to catch up with the calendar, which they don't do while the
+/// APB clock is stopped.  Call it after a change and after waking from Stop, before `now`.
+pub fn resync() {
+    // RSF is write-protected; only ISR[13:8] aren't.
+    unlocked(|rtc| rtc.isr.modify(|_, w| w.rsf().clear_bit()));
+    let rtc = rtc();
+    // Never more than two RTC clock periods, unless the RTC isn't running at all.
+    let start = Instant::now();
+    while rtc.isr.read().rsf().bit_is_clear() && Instant::now() - start < Duration::from_millis(1) {}
+}
+
+/// Run `f` with the RTC registers unlocked and interrupts masked, for this module and the
+/// wake-up timer in `power`.
+pub fn unlocked<R>(f: impl FnOnce(&stm32::rtc::RegisterBlock) -> R) -> R {
+    cortex_m::interrupt::free(|_| {
+        let rtc = rtc();
+        rtc.wpr.write(|w| unsafe { w.bits(0xCA) });
+        rtc.wpr.write(|w| unsafe { w.bits(0x53) });
+        let result = f(rtc);
+        rtc.wpr.write(|w| unsafe { w.bits(0xFF) });
+        result
+    })
+}
+
+/// Stop the calendar for changes to it or to the prescalers.
+fn enter_init(rtc: &stm32::rtc::RegisterBlock) {
+    rtc.isr.modify(|_, w| w.init().set_bit());
+    while rtc.isr.read().initf().bit_is_clear() {}
+}
+
+fn exit_init(rtc: &stm32::rtc::RegisterBlock) {
+    rtc.isr.modify(|_, w| w.init().clear_bit());
+}
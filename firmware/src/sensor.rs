@@ -0,0 +1,262 @@
+//! LSM303AGR accelerometer/magnetometer on I2C1, sampled at a rate set by TIM6.
+//!
+//! The driver owns the I2C bus, so an address scan has to take the bus back from it and
+//! re-initialise the sensor afterwards.  `Sensor` hides that juggling.
+//!
+//! The driver sets the sensor up, but the samples are read by DMA (see i2c_dma.rs), so the
+//! 1 ms or so each read takes at 100 kHz doesn't hold up the main loop: `start_reading` reads
+//! the accelerometer's output registers in the background, `reading` starts on the
+//! magnetometer's once they're in, and hands back both when those are in too.
+
+use core::cell::RefCell;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::interrupt::{free, Mutex};
+use lsm303agr::interface::I2cInterface;
+use lsm303agr::mode::MagContinuous;
+use lsm303agr::{AccelOutputDataRate, Lsm303agr, MagOutputDataRate};
+
+use stm32f3xx_hal as hal;
+use hal::gpio::gpiob::{PB6, PB7};
+use hal::gpio::AF4;
+use hal::prelude::*;
+use hal::stm32::{self, interrupt};
+use hal::timer::Timer;
+
+use beginstm_shared::lsm303::{self, ACCEL_ADDRESS, ACCEL_OUT, MAG_ADDRESS, MAG_OUT, OUT_LEN};
+use beginstm_shared::protocol::{I2cMap, Vector3};
+use crate::i2c_dma;
+
+pub type I2cBus = hal::i2c::I2c<stm32::I2C1, (PB6<AF4>, PB7<AF4>)>;
+type Device = Lsm303agr<I2cInterface<I2cBus>, MagContinuous>;
+
+/// Addresses outside this range are reserved by the I2C spec; don't probe them.
+const VALID_ADDR_RANGE: Range<u8> = 0x08..0x78;
+/// Sample rates the sensor can keep up with.
+pub const SAMPLE_RATE_RANGE: Range<u16> = 1..101;
+
+static SAMPLE_TIM: Mutex<RefCell<Option<Timer<stm32::TIM6>>>> = Mutex::new(RefCell::new(None));
+static SAMPLE_DUE: AtomicBool = AtomicBool::new(false);
+
+#[interrupt]
+// Sample timer: flag the main loop to read the sensor.
+fn TIM6_DACUNDER() {
+    free(|cs| {
+        if let Some(ref mut tim6) = *SAMPLE_TIM.borrow(cs).borrow_mut() {
+            tim6.clear_update_interrupt_flag()
+        }
+    });
+    SAMPLE_DUE.store(true, Ordering::Relaxed);
+}
+
+/// One sample of both sensors; None for a sensor whose read failed.
+#[derive(Clone, Copy, Default)]
+pub struct Reading {
+    pub accel: Option<Vector3>,
+    pub mag: Option<Vector3>,
+}
+
+/// How far the reads for a sample have got.
+#[derive(Clone, Copy)]
+enum Burst {
+    Idle,
+    Accel,
+    /// Reading the magnetometer, after the accelerometer.
+    Mag(Option<Vector3>),
+    /// Done, or never started for want of a sensor.
+    Ready(Reading),
+}
+
+pub struct Sensor {
+    // Exactly one of these is Some, except transiently inside a method.
+    dev: Option<Device>,
+    bus: Option<I2cBus>,
+    burst: Burst,
+    rate_hz: u16,
+    errors: u32,
+}
+
+impl Sensor {
+    /// Take the bus, the sample timer and DMA1 for reading, and start sampling at `rate_hz`.
+    /// The caller unmasks the TIM6_DACUNDER, I2C1_EV_EXTI23 and I2C1_ER interrupts.
+    pub fn new(i2c: I2cBus, mut timer: Timer<stm32::TIM6>, dma: stm32::DMA1, rate_hz: u16) -> Self {
+        i2c_dma::init(dma);
+        timer.start((rate_hz as u32).hz());
+        timer.listen(hal::timer::Event::Update);
+        free(|cs| {
+            SAMPLE_TIM.borrow(cs).replace(Some(timer));
+        });
+
+        let mut sensor = Sensor {
+            dev: None,
+            bus: Some(i2c),
+            burst: Burst::Idle,
+            rate_hz,
+            errors: 0,
+        };
+        sensor.attach();
+        sensor
+    }
+
+    /// True once per sample period; clears itself.
+    pub fn sample_due(&self) -> bool {
+        SAMPLE_DUE.swap(false, Ordering::AcqRel)
+    }
+
+    /// Start reading a sample, unless the last one is still being read.
+    pub fn start_reading(&mut self) {
+        if let Burst::Idle = self.burst {
+            self.burst = if self.dev.is_some() && self.begin(ACCEL_ADDRESS, ACCEL_OUT) {
+                Burst::Accel
+            } else {
+                Burst::Ready(Reading::default())
+            };
+        }
+    }
+
+    /// The sample `start_reading` started, once it's all in.
+    pub fn reading(&mut self) -> Option<Reading> {
+        match self.burst {
+            Burst::Idle => None,
+            Burst::Accel => {
+                let accel = self.take()?.map(|raw| lsm303::accel(&raw));
+                if self.begin(MAG_ADDRESS, MAG_OUT) {
+                    self.burst = Burst::Mag(accel);
+                    None
+                } else {
+                    self.burst = Burst::Idle;
+                    Some(Reading { accel, mag: None })
+                }
+            }
+            Burst::Mag(accel) => {
+                let mag = self.take()?.map(|raw| lsm303::mag(&raw));
+                self.burst = Burst::Idle;
+                Some(Reading { accel, mag })
+            }
+            Burst::Ready(reading) => {
+                self.burst = Burst::Idle;
+                Some(reading)
+            }
+        }
+    }
+
+    /// Start reading the output registers from `register` on.  False if that failed.
+    fn begin(&mut self, address: u8, register: u8) -> bool {
+        let started = i2c_dma::start(address, &[register], OUT_LEN).is_ok();
+        if !started {
+            self.errors += 1;
+        }
+        started
+    }
+
+    /// The registers read, once the read has ended: None while it runs, Some(None) if it
+    /// failed.
+    fn take(&mut self) -> Option<Option<[u8; OUT_LEN]>> {
+        let mut raw = [0; OUT_LEN];
+        match i2c_dma::take(&mut raw)? {
+            Ok(()) => Some(Some(raw)),
+            Err(_) => {
+                self.errors += 1;
+                Some(None)
+            }
+        }
+    }
+
+    /// Count of failed I2C transactions with the sensor.
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    pub fn rate_hz(&self) -> u16 {
+        self.rate_hz
+    }
+
+    /// Change the sample rate, and the sensor's output data rates to match.
+    /// Returns false if `hz` is outside `SAMPLE_RATE_RANGE`.
+    pub fn set_rate(&mut self, hz: u16) -> bool {
+        if !SAMPLE_RATE_RANGE.contains(&hz) {
+            return false;
+        }
+        self.rate_hz = hz;
+        i2c_dma::wait();
+        free(|cs| {
+            if let Some(ref mut tim6) = *SAMPLE_TIM.borrow(cs).borrow_mut() {
+                tim6.start((hz as u32).hz());
+            }
+        });
+        if let Some(ref mut dev) = self.dev {
+            let accel_ok = dev.set_accel_odr(accel_odr(hz)).is_ok();
+            let mag_ok = dev.set_mag_odr(mag_odr(hz)).is_ok();
+            if !(accel_ok && mag_ok) {
+                self.errors += 1;
+            }
+        }
+        true
+    }
+
+    /// Probe every valid address on the bus.
+    pub fn scan(&mut self) -> I2cMap {
+        let mut map = I2cMap::default();
+        i2c_dma::wait();
+        if let Some(dev) = self.dev.take() {
+            self.bus = Some(dev.destroy());
+        }
+        if let Some(ref mut bus) = self.bus {
+            for addr in VALID_ADDR_RANGE {
+                // Write the empty array and check the external device response.
+                if bus.write(addr, &[]).is_ok() {
+                    map.insert(addr);
+                }
+            }
+        }
+        self.attach();
+        map
+    }
+
+    /// Build the driver on the bus and configure the sensor.
+    fn attach(&mut self) {
+        let bus = match self.bus.take() {
+            Some(bus) => bus,
+            None => return,
+        };
+        let mut dev = Lsm303agr::new_with_i2c(bus);
+        if dev.init().is_err() || dev.set_accel_odr(accel_odr(self.rate_hz)).is_err() {
+            self.errors += 1;
+        }
+        // The magnetometer starts in one-shot mode; continuous mode lets mag_data() just read the latest sample.
+        match dev.into_mag_continuous() {
+            Ok(mut dev) => {
+                if dev.set_mag_odr(mag_odr(self.rate_hz)).is_err() {
+                    self.errors += 1;
+                }
+                self.dev = Some(dev);
+            }
+            Err(e) => {
+                self.errors += 1;
+                self.bus = Some(e.dev.destroy());
+            }
+        }
+    }
+}
+
+/// Slowest accelerometer data rate that keeps up with `hz`.
+fn accel_odr(hz: u16) -> AccelOutputDataRate {
+    match hz {
+        0..=1 => AccelOutputDataRate::Hz1,
+        2..=10 => AccelOutputDataRate::Hz10,
+        11..=25 => AccelOutputDataRate::Hz25,
+        26..=50 => AccelOutputDataRate::Hz50,
+        _ => AccelOutputDataRate::Hz100,
+    }
+}
+
+/// Slowest magnetometer data rate that keeps up with `hz`.
+fn mag_odr(hz: u16) -> MagOutputDataRate {
+    match hz {
+        0..=10 => MagOutputDataRate::Hz10,
+        11..=20 => MagOutputDataRate::Hz20,
+        21..=50 => MagOutputDataRate::Hz50,
+        _ => MagOutputDataRate::Hz100,
+    }
+}
@@ -0,0 +1,119 @@
+//! 8-bit dimming of the compass LEDs that aren't on timer outputs, by bit-angle modulation.
+//!
+//! LED n is PE(8 + n).  Each frame shows the eight bits of every LED's level one after the
+//! other, bit k for 2^k time units, so a frame is 255 units and the LED is on for `level` of
+//! them.  TIM16 interrupts at the end of each bit, which only costs 8 interrupts a frame
+//! whatever the levels are, where counting PWM would need 255.  With 16 µs units a frame lasts
+//! 4.08 ms, a 245 Hz refresh, well clear of visible flicker.
+//!
+//! All the LEDs are on port E, so one write to GPIOE's BSRR switches every pin for the next bit.
+//! The modulation itself is `beginstm_shared::isr::BitAngle`, tested on the host.
+//! The interrupt counts the cycles it spends; `stats` reports them, to show what the dimming
+//! costs in CPU time.  A late interrupt (e.g. behind a long USB poll) stretches one bit, which
+//! can show as a faint flicker of the low levels, never of the high ones.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m::peripheral::DWT;
+
+use stm32f3xx_hal as hal;
+use hal::rcc::Clocks;
+use hal::stm32::{self, interrupt};
+
+use beginstm_shared::isr::BitAngle;
+use crate::leds::Led;
+use crate::clocks::apb2_timer_hz;
+use crate::regs::Regs;
+
+/// Length of the least significant bit.
+const UNIT_US: u16 = 16;
+
+struct SoftPwm {
+    tim: stm32::TIM16,
+    _pins: [Option<Led>; 8],
+    /// GPIOE pins 8 to 15.
+    modulation: BitAngle,
+    stats: Stats,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Stats {
+    pub interrupts: u32,
+    /// Core clock cycles spent in the interrupt handler, not counting entry and exit.
+    pub cycles: u32,
+}
+
+static SOFT_PWM: Mutex<RefCell<Option<SoftPwm>>> = Mutex::new(RefCell::new(None));
+
+#[interrupt]
+// End of a bit: show the next one.
+fn TIM1_UP_TIM16() {
+    let start = DWT::cycle_count();
+    free(|cs| {
+        if let Some(ref mut pwm) = *SOFT_PWM.borrow(cs).borrow_mut() {
+            // NOTE(unsafe) BSRR writes only touch the pins named in them, all of them ours.
+            pwm.modulation.next_bit(&Regs(&*pwm.tim), &Regs(unsafe { &*stm32::GPIOE::ptr() }));
+            pwm.stats.interrupts = pwm.stats.interrupts.wrapping_add(1);
+            pwm.stats.cycles = pwm.stats.cycles.wrapping_add(DWT::cycle_count().wrapping_sub(start));
+        }
+    });
+}
+
+/// Start dimming.  `pins[n]` is LED n, or None if something else owns that pin.  Every LED
+/// starts off.  The DWT cycle counter must be running and the caller
+/// unmasks the TIM1_UP_TIM16 interrupt.
+pub fn init(pins: [Option<Led>; 8], tim: stm32::TIM16, clocks: &Clocks) {
+    let mut mask = 0;
+    for (n, pin) in pins.iter().enumerate() {
+        if pin.is_some() {
+            mask |= 1 << (8 + n);
+        }
+    }
+
+    // Power the timer and reset it to a clean state.  Only this module touches these bits.
+    unsafe {
+        (*stm32::RCC::ptr()).apb2enr.modify(|_, w| w.tim16en().set_bit());
+        (*stm32::RCC::ptr()).apb2rstr.modify(|_, w| w.tim16rst().set_bit());
+        (*stm32::RCC::ptr()).apb2rstr.modify(|_, w| w.tim16rst().clear_bit());
+    }
+    // Tick every microsecond.
+    tim.psc.write(|w| w.psc().bits((apb2_timer_hz(clocks) / 1_000_000 - 1) as u16));
+    // Preload ARR, so each bit's length takes effect at the update that starts it.
+    tim.cr1.modify(|_, w| w.arpe().set_bit());
+    let mut modulation = BitAngle::new(mask, UNIT_US);
+    modulation.start(&Regs(&*tim));
+    tim.dier.write(|w| w.uie().set_bit());
+    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+    free(|cs| {
+        SOFT_PWM.borrow(cs).replace(Some(SoftPwm {
+            tim,
+            _pins: pins,
+            modulation,
+            stats: Stats::default(),
+        }));
+    });
+}
+
+/// Set LED n's brightness, 0 (off) to 255 (fully on).  The duty is linear in `level`.
+/// False if the LED isn't driven here.
+pub fn set(led: u8, level: u8) -> bool {
+    free(|cs| match SOFT_PWM.borrow(cs).borrow_mut().as_mut() {
+        Some(pwm) if led < 8 => pwm.modulation.set(8 + led as u32, level),
+        _ => false,
+    })
+}
+
+/// LED n's brightness, or None if the LED isn't driven here.
+pub fn level(led: u8) -> Option<u8> {
+    free(|cs| match SOFT_PWM.borrow(cs).borrow().as_ref() {
+        Some(pwm) if led < 8 => pwm.modulation.level(8 + led as u32),
+        _ => None,
+    })
+}
+
+/// Interrupts taken and cycles spent so far.  Both wrap.
+pub fn stats() -> Stats {
+    free(|cs| SOFT_PWM.borrow(cs).borrow().as_ref().map_or(Stats::default(), |pwm| pwm.stats))
+}
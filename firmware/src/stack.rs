@@ -0,0 +1,97 @@
+//! Stack overflow protection and a measure of how much of the stack has been used.
+//!
+//! The stack starts at the top of RAM and grows down towards the statics, which end at
+//! `__sheap`.  Nothing stops it from running into them, and an overflow would quietly corrupt
+//! whatever is there, such as the animation engine or the USB state.  `init` puts a read-only
+//! MPU region, the guard, just above the statics, so a push into it is a MemManage fault
+//! instead, which `crash` records and resets from.  A single stack frame bigger than the guard
+//! could step right over it, so keep big arrays out of locals.
+//!
+//! `init` also paints the free stack with a known word; `high_water` finds where the paint
+//! stops, the deepest the stack has been since.  Interrupt handlers use the same stack, so the
+//! figure covers them too.
+
+use core::ptr;
+
+use cortex_m::peripheral::MPU;
+use cortex_m::register::msp;
+
+/// Bytes kept clear below the stack, a power of two of at least 32 to suit the MPU.
+pub const GUARD_SIZE: u32 = 256;
+const PAINT: u32 = 0xDEAD_BEEF;
+/// Room left unpainted below the stack pointer for `paint`'s own calls.
+const PAINT_MARGIN: u32 = 256;
+
+const MPU_CTRL_ENABLE: u32 = 1 << 0;
+const MPU_CTRL_PRIVDEFENA: u32 = 1 << 2;
+const MPU_RBAR_VALID: u32 = 1 << 4;
+const MPU_RASR_ENABLE: u32 = 1 << 0;
+/// Read-only, privileged or not; the fault handlers can still read a frame stacked here.
+const MPU_RASR_AP_READ_ONLY: u32 = 0b110 << 24;
+const MPU_RASR_XN: u32 = 1 << 28;
+const GUARD_REGION: u32 = 0;
+
+extern "C" {
+    /// End of the statics, from the cortex-m-rt linker script.
+    static __sheap: u32;
+    /// Top of the stack.
+    static _stack_start: u32;
+}
+
+/// The lowest address the stack may use, just above the guard.
+fn bottom() -> u32 {
+    guard_base() + GUARD_SIZE
+}
+
+fn top() -> u32 {
+    // NOTE(unsafe) only the symbol's address is used.
+    unsafe { &_stack_start as *const u32 as u32 }
+}
+
+/// The guard sits on the first boundary of its own size after the statics, as the MPU needs.
+fn guard_base() -> u32 {
+    // NOTE(unsafe) only the symbol's address is used.
+    let end = unsafe { &__sheap as *const u32 as u32 };
+    (end + GUARD_SIZE - 1) & !(GUARD_SIZE - 1)
+}
+
+/// Turn on the guard and paint the free stack.  Call it first thing in `main`, while the stack
+/// is shallow.
+pub fn init(mpu: MPU) {
+    // NOTE(unsafe) the MPU is ours.  The background map stays for everything else.
+    unsafe {
+        mpu.rnr.write(GUARD_REGION);
+        mpu.rbar.write(guard_base() | MPU_RBAR_VALID | GUARD_REGION);
+        let size = GUARD_SIZE.trailing_zeros() - 1;
+        mpu.rasr.write(MPU_RASR_XN | MPU_RASR_AP_READ_ONLY | (size << 1) | MPU_RASR_ENABLE);
+        mpu.ctrl.write(MPU_CTRL_PRIVDEFENA | MPU_CTRL_ENABLE);
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    paint();
+}
+
+fn paint() {
+    let end = msp::read() - PAINT_MARGIN;
+    let mut address = bottom();
+    while address < end {
+        // NOTE(unsafe) between the guard and the live stack, nothing else is there.
+        unsafe { ptr::write_volatile(address as *mut u32, PAINT) };
+        address += 4;
+    }
+}
+
+/// Bytes between the guard and the top of RAM.
+pub fn size() -> u32 {
+    top() - bottom()
+}
+
+/// The most stack used since `init`, in bytes.
+pub fn high_water() -> u32 {
+    let mut address = bottom();
+    // NOTE(unsafe) reads of RAM between the guard and the top of the stack.
+    while address < top() && unsafe { ptr::read_volatile(address as *const u32) } == PAINT {
+        address += 4;
+    }
+    top() - address
+}
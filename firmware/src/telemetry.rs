@@ -0,0 +1,35 @@
+//! Binary telemetry over the USB serial port, using the protocol in `beginstm-shared`.
+//!
+//! Sensor samples, button events and status counters go out as framed packets that the host
+//! tool decodes.  Console text goes out as `Log` packets (see `console.rs`), so the USB
+//! stream is never a mix of raw text and binary.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{free, Mutex};
+
+use beginstm_shared::protocol::{Encoder, Message, MAX_FRAME_LEN};
+
+use crate::time;
+use crate::usb_serial::USB_SERIAL;
+
+static ENCODER: Mutex<RefCell<Encoder>> = Mutex::new(RefCell::new(Encoder::new()));
+
+/// Frame `message` and queue it on the USB serial port.
+///
+/// Frames are sent whole or not at all; a half-sent frame would just cost the host a decode
+/// error.  Dropped frames still use up a sequence number, so the host can count them.
+pub fn send(message: Message) {
+    let timestamp = time::uptime_ms();
+    free(|cs| {
+        if let Some(ref mut serial) = *USB_SERIAL.borrow(cs).borrow_mut() {
+            if !serial.is_connected() {
+                return;
+            }
+            let mut frame = [0u8; MAX_FRAME_LEN];
+            if let Ok(len) = ENCODER.borrow(cs).borrow_mut().encode(message, timestamp, &mut frame) {
+                serial.write_all(&frame[..len]);
+            }
+        }
+    });
+}
@@ -0,0 +1,235 @@
+//! TIM1 PWM with the regular and complementary output of a channel driven together.
+//!
+//! The HAL's `pwm` module lets a channel use either its regular pin (CHx) or its complementary
+//! pin (CHxN) but not both.  Here both can be on at once, as a half-bridge needs: CHxN is the
+//! inverse of CHx, and the dead-time generator holds both inactive for a moment at each edge so
+//! the two switches are never on together.  The BDTR register also sets up the break input,
+//! which forces every output to its idle level, for instance on an over-current signal.
+//!
+//! On the Discovery board the TIM1 pins (all AF2) are LEDs:
+//!
+//! ``` text
+//! CH1 PE9   CH1N PE8
+//! CH2 PE11  CH2N PE10
+//! CH3 PE13  CH3N PE12
+//! CH4 PE14
+//! ```
+//!
+//! The break input, BKIN, is PE15 (AF2, the last LED), PA6 (AF6) or PB12 (AF6); configure the
+//! pin before enabling the break.
+
+use stm32f3xx_hal as hal;
+use hal::gpio::gpioe::{PE10, PE11, PE12, PE13, PE14, PE8, PE9};
+use hal::gpio::AF2;
+use hal::rcc::Clocks;
+use hal::stm32::{RCC, TIM1};
+use hal::time::Hertz;
+
+use beginstm_shared::pwm;
+use crate::clocks::apb2_timer_hz;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The PWM frequency can't be made from the timer clock.
+    Frequency,
+    /// The dead time is longer than the dead-time generator can count.
+    DeadTime,
+}
+
+/// What the break input does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Break {
+    Off,
+    #[allow(dead_code)]
+    ActiveLow,
+    ActiveHigh,
+}
+
+/// Levels the outputs of one channel go to when the outputs are off, i.e. after a break.
+/// Both high is not a sensible choice for a half-bridge.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Idle {
+    pub high: bool,
+    pub n_high: bool,
+}
+
+pub struct Config {
+    pub frequency: Hertz,
+    /// Gap between one output of a pair switching off and the other switching on.
+    pub dead_time_ns: u32,
+    pub brk: Break,
+    /// Turn the outputs back on by themselves at the first update after a break goes away.
+    /// Otherwise `resume` has to be called.
+    pub auto_resume: bool,
+    /// Idle levels of CH1 to CH4.  CH4 has no complementary output.
+    pub idle: [Idle; 4],
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            frequency: Hertz(1000),
+            dead_time_ns: 0,
+            brk: Break::Off,
+            auto_resume: false,
+            idle: [Idle::default(); 4],
+        }
+    }
+}
+
+pub struct Tim1 {
+    tim: TIM1,
+}
+
+impl Tim1 {
+    /// Set TIM1 counting with every channel in PWM mode 1 and every output still disabled.
+    pub fn new(tim: TIM1, config: Config, clocks: &Clocks) -> Result<Self, Error> {
+        let clock_hz = apb2_timer_hz(clocks);
+        let (prescale, reload) = pwm::timing(clock_hz, config.frequency.0).ok_or(Error::Frequency)?;
+        // CKD is left at zero, so the dead-time clock is the timer clock.
+        let dtg = pwm::dead_time(pwm::dead_time_ticks(config.dead_time_ns, clock_hz)).ok_or(Error::DeadTime)?;
+
+        // Power the timer and reset it to a clean state.  Only this driver touches these bits.
+        unsafe {
+            (*RCC::ptr()).apb2enr.modify(|_, w| w.tim1en().set_bit());
+            (*RCC::ptr()).apb2rstr.modify(|_, w| w.tim1rst().set_bit());
+            (*RCC::ptr()).apb2rstr.modify(|_, w| w.tim1rst().clear_bit());
+        }
+
+        tim.psc.write(|w| w.psc().bits(prescale));
+        tim.arr.write(|w| w.arr().bits(reload));
+        tim.cr1.modify(|_, w| w.arpe().set_bit());
+        tim.ccmr1_output().write(|w| {
+            w.oc1m().pwm_mode1().oc1pe().set_bit();
+            w.oc2m().pwm_mode1().oc2pe().set_bit()
+        });
+        tim.ccmr2_output().write(|w| {
+            w.oc3m().pwm_mode1().oc3pe().set_bit();
+            w.oc4m().pwm_mode1().oc4pe().set_bit()
+        });
+        let idle = config.idle;
+        tim.cr2.write(|w| {
+            w.ois1().bit(idle[0].high).ois1n().bit(idle[0].n_high);
+            w.ois2().bit(idle[1].high).ois2n().bit(idle[1].n_high);
+            w.ois3().bit(idle[2].high).ois3n().bit(idle[2].n_high);
+            w.ois4().bit(idle[3].high)
+        });
+        // With LOCK off these could be changed later, but some of them lock together, so set
+        // everything in one write.  OSSI and OSSR make disabled outputs drive their inactive
+        // or idle level rather than float, so a half-bridge is never left undriven.
+        tim.bdtr.write(|w| unsafe {
+            w.dtg().bits(dtg).ossi().idle_level().ossr().idle_level();
+            w.bke().bit(config.brk != Break::Off).bkp().bit(config.brk == Break::ActiveHigh);
+            w.aoe().bit(config.auto_resume).moe().set_bit()
+        });
+        // Load the prescaler and preloaded registers now, then start counting.
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+
+        Ok(Tim1 { tim })
+    }
+
+    /// Channel 1 on PE9 and/or PE8.
+    pub fn ch1(&mut self, pin: Option<PE9<AF2>>, n_pin: Option<PE8<AF2>>) -> Channel {
+        Channel::new(0, pin.is_some(), n_pin.is_some())
+    }
+
+    /// Channel 2 on PE11 and/or PE10.
+    pub fn ch2(&mut self, pin: Option<PE11<AF2>>, n_pin: Option<PE10<AF2>>) -> Channel {
+        Channel::new(1, pin.is_some(), n_pin.is_some())
+    }
+
+    /// Channel 3 on PE13 and/or PE12.
+    #[allow(dead_code)]
+    pub fn ch3(&mut self, pin: Option<PE13<AF2>>, n_pin: Option<PE12<AF2>>) -> Channel {
+        Channel::new(2, pin.is_some(), n_pin.is_some())
+    }
+
+    /// Channel 4 on PE14.
+    #[allow(dead_code)]
+    pub fn ch4(&mut self, _pin: PE14<AF2>) -> Channel {
+        Channel::new(3, true, false)
+    }
+
+    /// True if the break input has fired since the last call.
+    #[allow(dead_code)]
+    pub fn break_occurred(&mut self) -> bool {
+        let fired = self.tim.sr.read().bif().bit_is_set();
+        if fired {
+            // The flags are rc_w0: writing 1 leaves them alone, so a read-modify-write could
+            // clear one set in between.  NOTE(unsafe) every bit but BIF is 1.
+            self.tim.sr.write(|w| unsafe { w.bits(!(1 << 7)) });
+        }
+        fired
+    }
+
+    /// Turn the outputs back on after a break, unless the break input is still active.
+    #[allow(dead_code)]
+    pub fn resume(&mut self) {
+        self.tim.bdtr.modify(|_, w| w.moe().set_bit());
+    }
+}
+
+/// One channel, with its regular output, complementary output or both.
+pub struct Channel {
+    index: u8,
+    /// CCER enable bits for the outputs that have pins.
+    enable_mask: u32,
+}
+
+impl Channel {
+    fn new(index: u8, regular: bool, complementary: bool) -> Self {
+        let mut enable_mask = 0;
+        if regular {
+            enable_mask |= 1 << (4 * index); // CCxE
+        }
+        if complementary {
+            enable_mask |= 1 << (4 * index + 2); // CCxNE
+        }
+        Channel { index, enable_mask }
+    }
+
+    fn tim(&self) -> &'static hal::stm32::tim1::RegisterBlock {
+        // NOTE(unsafe) each channel only touches its own CCR and CCER bits.
+        unsafe { &*TIM1::ptr() }
+    }
+}
+
+impl hal::hal::PwmPin for Channel {
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        let mask = self.enable_mask;
+        cortex_m::interrupt::free(|_| self.tim().ccer.modify(|r, w| unsafe { w.bits(r.bits() & !mask) }));
+    }
+
+    fn enable(&mut self) {
+        let mask = self.enable_mask;
+        cortex_m::interrupt::free(|_| self.tim().ccer.modify(|r, w| unsafe { w.bits(r.bits() | mask) }));
+    }
+
+    fn get_duty(&self) -> u16 {
+        let tim = self.tim();
+        match self.index {
+            0 => tim.ccr1.read().ccr().bits(),
+            1 => tim.ccr2.read().ccr().bits(),
+            2 => tim.ccr3.read().ccr().bits(),
+            _ => tim.ccr4.read().ccr().bits(),
+        }
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        self.tim().arr.read().arr().bits()
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        let tim = self.tim();
+        match self.index {
+            0 => tim.ccr1.write(|w| w.ccr().bits(duty)),
+            1 => tim.ccr2.write(|w| w.ccr().bits(duty)),
+            2 => tim.ccr3.write(|w| w.ccr().bits(duty)),
+            _ => tim.ccr4.write(|w| w.ccr().bits(duty)),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Monotonic microsecond clock on TIM2, the one 32-bit timer.
+//!
+//! TIM2 counts microseconds from 0 to 2^32 - 1 and wraps, with no interrupts needed to read
+//! it: `Instant::now()` is a single register read.  `Instant` and `Duration` come from the
+//! shared crate, which handles the wrap.  For timestamps that must keep counting past 71
+//! minutes, the TIM2 interrupt counts the wraps and `uptime_us` puts the two together.
+//!
+//! Use `Instant::now()` for timeouts and intervals and `delay` for busy-waits; bring the
+//! `Monotonic` trait into scope for `now()`.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::interrupt::free;
+
+use stm32f3xx_hal as hal;
+use hal::rcc::Clocks;
+use hal::stm32::{self, interrupt};
+
+pub use beginstm_shared::time::{Duration, Instant};
+use crate::clocks::apb1_timer_hz;
+
+/// Times TIM2 has wrapped.
+static WRAPS: AtomicU32 = AtomicU32::new(0);
+
+#[interrupt]
+// TIM2 wrapped: carry into the high word of the uptime.
+fn TIM2() {
+    tim2().sr.modify(|_, w| w.uif().clear_bit());
+    WRAPS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn tim2() -> &'static stm32::tim2::RegisterBlock {
+    // NOTE(unsafe) after init, the counter is only read and SR is only changed with
+    // interrupts masked or from the TIM2 interrupt itself.
+    unsafe { &*stm32::TIM2::ptr() }
+}
+
+/// Start the clock from zero.  Call it before anything asks for the time; the caller unmasks
+/// the TIM2 interrupt, without which only `uptime_us` is affected, after 71 minutes.
+pub fn init(tim: stm32::TIM2, clocks: &Clocks) {
+    // Power the timer and reset it to a clean state.  Only this module touches these bits.
+    unsafe {
+        (*stm32::RCC::ptr()).apb1enr.modify(|_, w| w.tim2en().set_bit());
+        (*stm32::RCC::ptr()).apb1rstr.modify(|_, w| w.tim2rst().set_bit());
+        (*stm32::RCC::ptr()).apb1rstr.modify(|_, w| w.tim2rst().clear_bit());
+    }
+    tim.psc.write(|w| w.psc().bits((apb1_timer_hz(clocks) / 1_000_000 - 1) as u16));
+    tim.arr.write(|w| unsafe { w.bits(u32::MAX) });
+    // Load the prescaler, then drop the update flag that comes with it.
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.sr.modify(|_, w| w.uif().clear_bit());
+    tim.dier.write(|w| w.uie().set_bit());
+    tim.cr1.modify(|_, w| w.cen().set_bit());
+}
+
+/// Something that can tell the current time.
+pub trait Monotonic {
+    fn now() -> Self;
+}
+
+impl Monotonic for Instant {
+    fn now() -> Instant {
+        Instant::from_micros(tim2().cnt.read().bits())
+    }
+}
+
+/// Microseconds since `init`, without wrapping (for half a million years).
+pub fn uptime_us() -> u64 {
+    free(|_| {
+        let tim = tim2();
+        let mut wraps = WRAPS.load(Ordering::Relaxed);
+        let mut count = tim.cnt.read().bits();
+        // A wrap that the interrupt hasn't counted yet, because interrupts are masked.
+        // The counter is read again in case it wrapped just after the first read.
+        if tim.sr.read().uif().bit_is_set() {
+            wraps += 1;
+            count = tim.cnt.read().bits();
+        }
+        ((wraps as u64) << 32) | count as u64
+    })
+}
+
+/// Milliseconds since `init`, wrapping after 49 days, for telemetry timestamps.
+pub fn uptime_ms() -> u32 {
+    (uptime_us() / 1000) as u32
+}
+
+/// True once `init` has started the clock, so that `delay` returns.
+pub fn is_running() -> bool {
+    tim2().cr1.read().cen().bit_is_set()
+}
+
+/// Busy-wait for at least `duration`, which must be under 35 minutes.
+pub fn delay(duration: Duration) {
+    let start = Instant::now();
+    while Instant::now() - start < duration {}
+}
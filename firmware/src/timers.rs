@@ -0,0 +1,84 @@
+//! Software timers, as many as `CAPACITY`, on TIM7 ticking every millisecond.
+//!
+//! The timing wheel itself is in the shared crate, where it is tested on the host.  The TIM7
+//! interrupt only counts ticks; `poll`, from the main loop, catches the wheel up and hands back
+//! what each expired timer carries, usually an enum of jobs for the caller to match on.  So the
+//! jobs run in thread mode, where they can take their time, and scheduling or cancelling needs
+//! no critical section.  A job that runs late doesn't push back the next run of a periodic
+//! timer.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use stm32f3xx_hal as hal;
+use hal::rcc::Clocks;
+use hal::stm32::{self, interrupt};
+
+use beginstm_shared::isr;
+use beginstm_shared::timer_wheel::{Full, TimerId, Wheel};
+use crate::clocks::apb1_timer_hz;
+use crate::regs::Regs;
+use crate::time::Duration;
+
+pub const CAPACITY: usize = 8;
+/// A turn of the wheel is 64 ms; timers further off than that wait out the extra turns.
+const SLOTS: usize = 64;
+const TICK_HZ: u32 = 1000;
+
+/// Ticks the interrupt has counted and `poll` hasn't taken yet.
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+#[interrupt]
+// One tick.
+fn TIM7() {
+    // NOTE(unsafe) after init, only this interrupt touches TIM7.
+    isr::tick(&Regs(unsafe { &*stm32::TIM7::ptr() }), &TICKS);
+}
+
+pub struct Timers<T> {
+    _tim: stm32::TIM7,
+    wheel: Wheel<T, CAPACITY, SLOTS>,
+}
+
+impl<T: Copy> Timers<T> {
+    /// Start TIM7 ticking.  The caller unmasks the TIM7 interrupt.
+    pub fn new(tim: stm32::TIM7, clocks: &Clocks) -> Self {
+        // Power the timer and reset it to a clean state.  Only this module touches these bits.
+        unsafe {
+            (*stm32::RCC::ptr()).apb1enr.modify(|_, w| w.tim7en().set_bit());
+            (*stm32::RCC::ptr()).apb1rstr.modify(|_, w| w.tim7rst().set_bit());
+            (*stm32::RCC::ptr()).apb1rstr.modify(|_, w| w.tim7rst().clear_bit());
+        }
+        // Count microseconds, up to a millisecond.
+        tim.psc.write(|w| w.psc().bits((apb1_timer_hz(clocks) / 1_000_000 - 1) as u16));
+        tim.arr.write(|w| w.arr().bits((1_000_000 / TICK_HZ - 1) as u16));
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.modify(|_, w| w.uif().clear_bit());
+        tim.dier.write(|w| w.uie().set_bit());
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+        TICKS.store(0, Ordering::Relaxed);
+
+        Timers { _tim: tim, wheel: Wheel::new() }
+    }
+
+    /// Hand back `job` once, `delay` from now, rounded to whole milliseconds (at least one).
+    pub fn once(&mut self, delay: Duration, job: T) -> Result<TimerId, Full> {
+        self.wheel.once(delay.as_millis(), job)
+    }
+
+    /// Hand back `job` every `period`, rounded to whole milliseconds (at least one).
+    pub fn every(&mut self, period: Duration, job: T) -> Result<TimerId, Full> {
+        self.wheel.every(period.as_millis(), job)
+    }
+
+    /// Stop a timer, returning its job if it hadn't finished.
+    #[allow(dead_code)]
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        self.wheel.cancel(id)
+    }
+
+    /// The next job that is due, if any.  Call until it returns None.
+    pub fn poll(&mut self) -> Option<T> {
+        self.wheel.advance(TICKS.swap(0, Ordering::Relaxed));
+        self.wheel.poll().map(|(_, job)| job)
+    }
+}
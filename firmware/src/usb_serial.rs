@@ -0,0 +1,328 @@
+//! USB CDC-ACM serial port on the Discovery board's "USB USER" connector.
+//!
+//! The STM32F303 USB full-speed peripheral is wired to PA11 (D-) and PA12 (D+).
+//! `usb-device` handles enumeration and the control pipe; this module supplies the
+//! CDC-ACM class (the bit that makes Linux create `/dev/ttyACM0`) and a small
+//! buffered `UsbSerial` wrapper that the console and the interrupt handlers share.
+//!
+//! The USB peripheral needs a 48 MHz clock, which on this part can only come from the
+//! PLL fed by the HSE.  Check `clocks.usbclk_valid()` before calling `UsbBus::new()`.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use cortex_m::interrupt::Mutex;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+use usb_device::prelude::*;
+use usb_device::Result;
+
+use stm32f3xx_hal::usb::UsbBusType;
+
+use beginstm_shared::ring::Ring;
+
+// Class, subclass and protocol codes from the USB CDC 1.1 and PSTN specifications.
+const USB_CLASS_CDC: u8 = 0x02;
+const USB_CLASS_CDC_DATA: u8 = 0x0a;
+const CDC_SUBCLASS_ACM: u8 = 0x02;
+const CDC_PROTOCOL_NONE: u8 = 0x00;
+
+// Class-specific descriptor types and subtypes.
+const CS_INTERFACE: u8 = 0x24;
+const CDC_TYPE_HEADER: u8 = 0x00;
+const CDC_TYPE_CALL_MANAGEMENT: u8 = 0x01;
+const CDC_TYPE_ACM: u8 = 0x02;
+const CDC_TYPE_UNION: u8 = 0x06;
+
+// Class-specific requests.
+const REQ_SEND_ENCAPSULATED_COMMAND: u8 = 0x00;
+const REQ_GET_ENCAPSULATED_COMMAND: u8 = 0x01;
+const REQ_SET_LINE_CODING: u8 = 0x20;
+const REQ_GET_LINE_CODING: u8 = 0x21;
+const REQ_SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+/// Bulk endpoint packet size.  64 bytes is the maximum for full-speed bulk transfers.
+const MAX_PACKET_SIZE: u16 = 64;
+/// Size of the transmit buffer between the console and the bulk IN endpoint.
+const TX_BUFFER_SIZE: usize = 512;
+/// Size of the receive buffer between the bulk OUT endpoint and `read`: a few commands' worth.
+const RX_BUFFER_SIZE: usize = 256;
+
+/// Obviously fake VID/PID pair shared by hobby CDC-ACM devices.  Linux binds `cdc_acm` by class,
+/// so the exact values don't matter for enumeration.
+const VID_PID: UsbVidPid = UsbVidPid(0x16c0, 0x27dd);
+
+/// The USB serial port, shared between `main` (writes) and the USB interrupts (polling).
+pub static USB_SERIAL: Mutex<RefCell<Option<UsbSerial<'static, UsbBusType>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Line coding requested by the host with SET_LINE_CODING.
+///
+/// The baud rate etc. are meaningless for a USB virtual port, but the host expects to read back
+/// what it set, and some terminal programs refuse to open the port otherwise.
+#[derive(Clone, Copy, Debug)]
+pub struct LineCoding {
+    pub data_rate: u32,
+    pub stop_bits: u8,
+    pub parity: u8,
+    pub data_bits: u8,
+}
+
+impl Default for LineCoding {
+    fn default() -> Self {
+        LineCoding {
+            data_rate: 115_200,
+            stop_bits: 0, // 1 stop bit
+            parity: 0,    // None
+            data_bits: 8,
+        }
+    }
+}
+
+/// CDC Abstract Control Model class: a communication interface with a notification endpoint and
+/// a data interface with a pair of bulk endpoints.
+pub struct CdcAcmClass<'a, B: UsbBus> {
+    comm_if: InterfaceNumber,
+    comm_ep: EndpointIn<'a, B>,
+    data_if: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    line_coding: LineCoding,
+    dtr: bool,
+}
+
+impl<'a, B: UsbBus> CdcAcmClass<'a, B> {
+    pub fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        CdcAcmClass {
+            comm_if: alloc.interface(),
+            comm_ep: alloc.interrupt(8, 255),
+            data_if: alloc.interface(),
+            read_ep: alloc.bulk(MAX_PACKET_SIZE),
+            write_ep: alloc.bulk(MAX_PACKET_SIZE),
+            line_coding: LineCoding::default(),
+            dtr: false,
+        }
+    }
+
+    /// Data Terminal Ready, set by the host when a program opens the tty.
+    pub fn dtr(&self) -> bool {
+        self.dtr
+    }
+
+    pub fn write_packet(&mut self, data: &[u8]) -> Result<usize> {
+        self.write_ep.write(data)
+    }
+
+    pub fn read_packet(&mut self, data: &mut [u8]) -> Result<usize> {
+        self.read_ep.read(data)
+    }
+
+    pub fn max_packet_size(&self) -> u16 {
+        self.write_ep.max_packet_size()
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for CdcAcmClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.iad(self.comm_if, 2, USB_CLASS_CDC, CDC_SUBCLASS_ACM, CDC_PROTOCOL_NONE)?;
+
+        writer.interface(self.comm_if, USB_CLASS_CDC, CDC_SUBCLASS_ACM, CDC_PROTOCOL_NONE)?;
+        writer.write(CS_INTERFACE, &[CDC_TYPE_HEADER, 0x10, 0x01])?; // bcdCDC 1.10
+        writer.write(CS_INTERFACE, &[CDC_TYPE_CALL_MANAGEMENT, 0x00, self.data_if.into()])?;
+        writer.write(CS_INTERFACE, &[CDC_TYPE_ACM, 0x02])?; // Supports line coding and control line state.
+        writer.write(CS_INTERFACE, &[CDC_TYPE_UNION, self.comm_if.into(), self.data_if.into()])?;
+        writer.endpoint(&self.comm_ep)?;
+
+        writer.interface(self.data_if, USB_CLASS_CDC_DATA, 0x00, 0x00)?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.line_coding = LineCoding::default();
+        self.dtr = false;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if !(req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.comm_if) as u16)
+        {
+            return;
+        }
+
+        match req.request {
+            REQ_GET_ENCAPSULATED_COMMAND => {
+                xfer.accept(|_| Ok(0)).ok();
+            }
+            REQ_GET_LINE_CODING if req.length == 7 => {
+                let lc = self.line_coding;
+                xfer.accept(|data| {
+                    data[0..4].copy_from_slice(&lc.data_rate.to_le_bytes());
+                    data[4] = lc.stop_bits;
+                    data[5] = lc.parity;
+                    data[6] = lc.data_bits;
+                    Ok(7)
+                })
+                .ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !(req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.comm_if) as u16)
+        {
+            return;
+        }
+
+        match req.request {
+            REQ_SEND_ENCAPSULATED_COMMAND => {
+                // Nothing to do; accept so the host doesn't see a stall.
+                xfer.accept().ok();
+            }
+            REQ_SET_LINE_CODING if xfer.data().len() >= 7 => {
+                let data = xfer.data();
+                self.line_coding = LineCoding {
+                    data_rate: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+                    stop_bits: data[4],
+                    parity: data[5],
+                    data_bits: data[6],
+                };
+                xfer.accept().ok();
+            }
+            REQ_SET_CONTROL_LINE_STATE => {
+                self.dtr = req.value & 0x0001 != 0;
+                xfer.accept().ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
+
+/// A CDC-ACM device plus a transmit buffer, so callers can write more than one packet at a time
+/// and the bulk IN endpoint is refilled from the USB interrupt as packets complete.
+///
+/// Received packets are emptied into a receive buffer by the interrupt too: the endpoint keeps
+/// the interrupt asserted until its packet is read, so leaving that to the main loop would
+/// starve it.
+pub struct UsbSerial<'a, B: UsbBus> {
+    device: UsbDevice<'a, B>,
+    class: CdcAcmClass<'a, B>,
+    tx: Ring<TX_BUFFER_SIZE>,
+    rx: Ring<RX_BUFFER_SIZE>,
+    dropped: u32,
+}
+
+impl<'a, B: UsbBus> UsbSerial<'a, B> {
+    pub fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        let class = CdcAcmClass::new(alloc);
+        let device = UsbDeviceBuilder::new(alloc, VID_PID)
+            .manufacturer("beginstm")
+            .product("STM32F3DISCOVERY console")
+            .serial_number("0001")
+            .device_class(USB_CLASS_CDC)
+            .build();
+
+        UsbSerial {
+            device,
+            class,
+            tx: Ring::new(),
+            rx: Ring::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Service the USB peripheral.  Call from the USB interrupts (or often from a loop).
+    /// Returns true if there may be received data to read.
+    pub fn poll(&mut self) -> bool {
+        let event = self.device.poll(&mut [&mut self.class]);
+        self.receive();
+        self.flush();
+        event
+    }
+
+    /// True once the host has configured the device and a program has the tty open.
+    /// There is no point buffering output before then; nobody would read it.
+    pub fn is_connected(&self) -> bool {
+        self.device.state() == UsbDeviceState::Configured && self.class.dtr()
+    }
+
+    /// Queue bytes for transmission.  Returns the number accepted; the rest are counted as dropped.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        if !self.is_connected() {
+            return 0;
+        }
+        let written = self.tx.push(data);
+        self.dropped += (data.len() - written) as u32;
+        self.flush();
+        written
+    }
+
+    /// Queue all of `data`, or none of it if it doesn't fit.  Returns true if queued.
+    pub fn write_all(&mut self, data: &[u8]) -> bool {
+        if !self.is_connected() {
+            return false;
+        }
+        if self.tx.space() < data.len() {
+            self.dropped += data.len() as u32;
+            return false;
+        }
+        self.write(data);
+        true
+    }
+
+    /// Read whatever the host has sent.  Returns 0 if nothing is waiting.
+    pub fn read(&mut self, data: &mut [u8]) -> usize {
+        self.rx.pop(data)
+    }
+
+    /// Number of bytes discarded because the transmit buffer was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Move a received packet, if there is one, into the receive buffer.  What doesn't fit is
+    /// dropped, since the packet has to be read either way; the host retries a command that
+    /// gets no reply.
+    fn receive(&mut self) {
+        let mut packet = [0; MAX_PACKET_SIZE as usize];
+        if let Ok(len) = self.class.read_packet(&mut packet) {
+            self.rx.push(&packet[..len]);
+        }
+    }
+
+    /// Push the next packet from the transmit buffer if the endpoint will take it.
+    fn flush(&mut self) {
+        if self.tx.is_empty() {
+            return;
+        }
+        // Send the contiguous run up to the end of the buffer; the wrapped part goes next time.
+        let front = self.tx.front();
+        let count = front.len().min(self.class.max_packet_size() as usize);
+        match self.class.write_packet(&front[..count]) {
+            Ok(sent) => self.tx.consume(sent),
+            Err(UsbError::WouldBlock) => (), // Previous packet still in flight.
+            Err(_) => (),
+        }
+    }
+}
+
+impl<B: UsbBus> fmt::Write for UsbSerial<'_, B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
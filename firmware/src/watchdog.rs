@@ -0,0 +1,183 @@
+//! Independent watchdog, fed only while every task checks in on time.
+//!
+//! The IWDG runs from the LSI and resets the chip if it isn't fed within `TIMEOUT`.  Feeding it
+//! from the main loop alone would only catch the main loop hanging, so instead each logical task
+//! calls `check_in` whenever it makes progress, and the SysTick exception, every `CHECK_PERIOD`,
+//! feeds the watchdog only if none of them is past its deadline (see
+//! `beginstm_shared::supervisor`).  The first time one is, its number goes into RTC backup
+//! register 0, which survives the reset, and `last_missed` reports it after the restart.
+//!
+//! It starts early in `main`, before anything that could hang such as I2C traffic, which has no
+//! timeouts.  Until `started`, start-up is the only task watched, and `main` checks in for it
+//! between steps.
+//!
+//! A hang that stops SysTick as well, such as a fault, still resets the board but without a
+//! record.  The IWDG keeps counting in Stop and Standby, so with it running, `power` must not
+//! stay in either for longer than the timeout.  It is frozen while a debugger halts the core.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+use cortex_m_rt::exception;
+
+use stm32f3xx_hal as hal;
+use hal::rcc::Clocks;
+use hal::stm32;
+
+use beginstm_shared::supervisor::Supervisor;
+use crate::rtc;
+use crate::time::{Duration, Instant, Monotonic};
+
+/// The parts of the firmware that must keep making progress.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Task {
+    /// The main loop reading the sensors.
+    Sensor = 0,
+    /// The animation frames in the TIM4 interrupt.
+    Leds = 1,
+    /// The main loop serving host commands.
+    Comms = 2,
+    /// `main` setting everything up, until `started`.
+    Startup = 3,
+}
+
+const TASKS: [Task; 4] = [Task::Sensor, Task::Leds, Task::Comms, Task::Startup];
+/// Longest each task may go without checking in.  The sensor is read at 1 Hz at the slowest;
+/// start-up's longest step is a 1 s LED delay.
+const DEADLINES: [Duration; 4] = [
+    Duration::from_millis(2500),
+    Duration::from_millis(500),
+    Duration::from_millis(1000),
+    Duration::from_millis(1500),
+];
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+const CHECK_PERIOD: Duration = Duration::from_millis(100);
+/// Nominal LSI frequency; the timeout is only good to the LSI's few percent.
+const LSI_HZ: u32 = 40_000;
+
+/// Backup register that records the task that missed its deadline.
+const BACKUP_REGISTER: usize = 0;
+/// Marks the register as holding a record, in the top half; the task is in the bottom.
+const RECORD_MAGIC: u32 = 0xD06_0000;
+
+struct Watchdog {
+    iwdg: stm32::IWDG,
+    _syst: SYST,
+    supervisor: Supervisor<4>,
+    /// Set once a task is overdue; the watchdog is never fed again.
+    tripped: bool,
+}
+
+static WATCHDOG: Mutex<RefCell<Option<Watchdog>>> = Mutex::new(RefCell::new(None));
+
+#[exception]
+// Feed the watchdog if every task is on time.
+fn SysTick() {
+    free(|cs| {
+        if let Some(ref mut dog) = *WATCHDOG.borrow(cs).borrow_mut() {
+            if dog.tripped {
+                return;
+            }
+            match dog.supervisor.overdue(Instant::now()) {
+                None => dog.iwdg.kr.write(|w| w.key().reset()),
+                Some((task, _)) => {
+                    dog.tripped = true;
+                    rtc::set_backup(BACKUP_REGISTER, RECORD_MAGIC | task as u32);
+                }
+            }
+        }
+    });
+}
+
+/// Start the watchdog, which can't be stopped again short of a reset, and the SysTick that
+/// checks on the tasks, watching only start-up.  `rtc::init` must have run, for the backup
+/// register.
+pub fn start(iwdg: stm32::IWDG, mut syst: SYST, dbgmcu: &stm32::DBGMCU, clocks: &Clocks) {
+    dbgmcu.apb1_fz.modify(|_, w| w.dbg_iwdg_stop().set_bit());
+
+    // Starting the IWDG turns the LSI on; then unlock and set the prescaler and reload.
+    iwdg.kr.write(|w| w.key().start());
+    iwdg.kr.write(|w| w.key().enable());
+    let (prescaler, reload) = timing(TIMEOUT);
+    iwdg.pr.write(|w| w.pr().bits(prescaler));
+    iwdg.rlr.write(|w| w.rl().bits(reload));
+    while iwdg.sr.read().bits() != 0 {} // Wait for the values to reach the LSI domain.
+    iwdg.kr.write(|w| w.key().reset());
+
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(clocks.sysclk().0 / 1_000_000 * CHECK_PERIOD.as_micros() - 1);
+    syst.clear_current();
+    syst.enable_interrupt();
+    syst.enable_counter();
+
+    let mut supervisor = Supervisor::new(DEADLINES, Instant::now());
+    for task in [Task::Sensor, Task::Leds, Task::Comms] {
+        supervisor.ignore(task as usize);
+    }
+    free(|cs| {
+        WATCHDOG.borrow(cs).replace(Some(Watchdog {
+            iwdg,
+            _syst: syst,
+            supervisor,
+            tripped: false,
+        }));
+    });
+}
+
+/// Start-up is over: watch the other tasks, their deadlines counting from now.
+pub fn started() {
+    let now = Instant::now();
+    free(|cs| {
+        if let Some(ref mut dog) = *WATCHDOG.borrow(cs).borrow_mut() {
+            dog.supervisor.ignore(Task::Startup as usize);
+            for task in [Task::Sensor, Task::Leds, Task::Comms] {
+                dog.supervisor.watch(task as usize, now);
+            }
+        }
+    });
+}
+
+/// The IWDG prescaler setting (divide by 4 << setting) and reload for `timeout`.
+fn timing(timeout: Duration) -> (u8, u16) {
+    let mut prescaler = 0;
+    loop {
+        let tick_hz = LSI_HZ / (4 << prescaler);
+        let ticks = timeout.as_micros() as u64 * tick_hz as u64 / 1_000_000;
+        if ticks <= 0x1000 || prescaler == 6 {
+            return (prescaler, ticks.clamp(1, 0x1000) as u16 - 1);
+        }
+        prescaler += 1;
+    }
+}
+
+/// Feed the watchdog whatever the tasks are doing, for the panic handler, which holds the chip
+/// on purpose.  Harmless if the watchdog isn't running.
+pub fn feed() {
+    // NOTE(unsafe) a single write of the reload key, which only reloads the counter.
+    unsafe { (*stm32::IWDG::ptr()).kr.write(|w| w.key().reset()) };
+}
+
+/// `task` is making progress.  Fine to call from interrupts.
+pub fn check_in(task: Task) {
+    let now = Instant::now();
+    free(|cs| {
+        if let Some(ref mut dog) = *WATCHDOG.borrow(cs).borrow_mut() {
+            dog.supervisor.check_in(task as usize, now);
+        }
+    });
+}
+
+/// The task that missed its deadline and so caused the last reset, if that's what happened.
+/// Clears the record.
+pub fn last_missed() -> Option<Task> {
+    let record = rtc::backup(BACKUP_REGISTER);
+    rtc::set_backup(BACKUP_REGISTER, 0);
+    if record & 0xFFFF_0000 == RECORD_MAGIC {
+        TASKS.get((record & 0xFFFF) as usize).copied()
+    } else {
+        None
+    }
+}
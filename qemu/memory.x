@@ -0,0 +1,6 @@
+/* The LM3S6965 on QEMU's lm3s6965evb machine, which these programs run on. */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00000000, LENGTH = 256K
+  RAM : ORIGIN = 0x20000000, LENGTH = 64K
+}
@@ -0,0 +1,141 @@
+//! Builds the programs in `qemu/src/bin` and the firmware examples, and runs them headless on an
+//! emulated Cortex-M with semihosting, for the tests in `tests/`.
+//!
+//! The programs in `qemu/` are linked for QEMU's `lm3s6965evb`, a Cortex-M3.  The firmware
+//! examples are linked for the STM32F303 with the firmware's own `memory.x`, so they run on
+//! `netduinoplus2` instead: an STM32F405, a Cortex-M4F with flash, RAM and CCM at the same
+//! addresses.  Only examples that stick to the core and semihosting run there, since its
+//! peripherals aren't the F303's.
+//!
+//! This is a host crate in the root workspace; `qemu/` and `firmware/` are built with their own
+//! `.cargo/config.toml`, which set the Thumb targets.
+
+use std::env;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest a program may run; they finish in well under a second.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How a program ended.
+pub struct Run {
+    /// None if QEMU was killed by a signal.
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Build `program` from `qemu/src/bin` and run it to the end.
+pub fn program(program: &str) -> Run {
+    let dir = repository().join("qemu");
+    build(&dir, &["--bin", program]);
+    let kernel = dir.join("target/thumbv7m-none-eabi/release").join(program);
+    run(&["-cpu", "cortex-m3", "-machine", "lm3s6965evb"], &kernel)
+}
+
+/// Build `example` from `firmware/examples` with `features` and run it to the end.
+pub fn example(example: &str, features: &[&str]) -> Run {
+    let dir = repository().join("firmware");
+    let features = features.join(",");
+    let mut args = vec!["--example", example];
+    if !features.is_empty() {
+        args.extend(["--features", &features]);
+    }
+    build(&dir, &args);
+    let kernel = dir.join("target/thumbv7em-none-eabihf/release/examples").join(example);
+    run(&["-machine", "netduinoplus2"], &kernel)
+}
+
+fn repository() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../..")
+}
+
+fn build(dir: &Path, args: &[&str]) {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let built = Command::new(cargo)
+        .current_dir(dir)
+        .args(["build", "--release"])
+        .args(args)
+        .env_remove("CARGO_TARGET_DIR")
+        .status()
+        .expect("cargo runs");
+    assert!(built.success(), "building {:?} in {} failed", args, dir.display());
+}
+
+fn run(machine: &[&str], kernel: &Path) -> Run {
+    let mut qemu = Command::new("qemu-system-arm");
+    qemu.args(machine)
+        .args(["-nographic", "-semihosting-config", "enable=on,target=native", "-kernel"])
+        .arg(kernel);
+    wait(qemu, TIMEOUT)
+}
+
+/// Run `command` to the end, killing it after `timeout`.
+fn wait(mut command: Command, timeout: Duration) -> Run {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| panic!("{:?} doesn't run: {}", command, e));
+
+    // Empty the pipes as the program writes, or it stops once one is full.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().expect("waiting for the program") {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            child.kill().ok();
+            child.wait().ok();
+            panic!("{:?} still running after {:?}", command, timeout);
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    Run {
+        status: status.code(),
+        stdout: stdout.join().expect("reading stdout"),
+        stderr: stderr.join().expect("reading stderr"),
+    }
+}
+
+/// Read `pipe` to the end on a thread of its own.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut bytes).ok();
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    #[test]
+    fn output_bigger_than_a_pipe_is_all_read() {
+        let run = wait(sh("head -c 200000 /dev/zero | tr '\\0' x; echo oops >&2; exit 3"), TIMEOUT);
+        assert_eq!(run.status, Some(3));
+        assert_eq!(run.stdout.len(), 200_000);
+        assert_eq!(run.stderr, "oops\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "still running")]
+    fn a_program_that_hangs_is_killed() {
+        wait(sh("sleep 10"), Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,71 @@
+//! Runs the programs in `qemu/` and the firmware examples on an emulated Cortex-M and checks
+//! what they print and how they exit, so the shared code is tested as Thumb code as well as on
+//! the host.
+//!
+//! They need `qemu-system-arm` on the PATH and the `thumbv7m-none-eabi` and
+//! `thumbv7em-none-eabihf` targets installed, so they are ignored by default.  From the
+//! repository root:
+//!
+//! ``` console
+//! $ rustup target add thumbv7m-none-eabi thumbv7em-none-eabihf
+//! $ cargo test -p beginstm-qemu-runner -- --ignored
+//! ```
+
+use beginstm_qemu_runner::{example, program};
+
+#[test]
+#[ignore = "needs qemu-system-arm and the thumbv7em-none-eabihf target"]
+fn hello_example_prints_and_exits_successfully() {
+    let run = example("hello", &[]);
+    assert_eq!(run.stdout, "Hello, world!\n");
+    assert_eq!(run.status, Some(0), "{}", run.stderr);
+}
+
+#[test]
+#[ignore = "needs qemu-system-arm and the thumbv7em-none-eabihf target"]
+fn allocator_example_prints_its_vector() {
+    let run = example("allocator", &["allocator"]);
+    assert_eq!(run.stdout, "[0, 1, 2]\n");
+    assert_eq!(run.status, Some(0), "{}", run.stderr);
+}
+
+#[test]
+#[ignore = "needs qemu-system-arm and the thumbv7m-none-eabi target"]
+fn shared_code_passes_on_thumb() {
+    let run = program("shared");
+    assert_eq!(run.status, Some(0), "{}{}", run.stdout, run.stderr);
+    let results: Vec<&str> = run.stdout.lines().filter(|line| line.starts_with("test ")).collect();
+    assert_eq!(
+        results,
+        [
+            "test crc ... ok",
+            "test protocol_round_trip ... ok",
+            "test calendar ... ok",
+            "test timer_wheel ... ok",
+            "test time_across_the_wrap ... ok",
+            "test result: ok. 5 passed; 0 failed",
+        ]
+    );
+}
+
+#[test]
+#[ignore = "needs qemu-system-arm and the thumbv7m-none-eabi target"]
+fn failed_tests_fail_the_run_but_not_the_tests_after_them() {
+    let run = program("fail");
+    assert_eq!(run.status, Some(1));
+    let results: Vec<&str> = run.stdout.lines().filter(|line| line.starts_with("test ")).collect();
+    assert_eq!(
+        results,
+        [
+            "test passes ... ok",
+            "test fails ... FAILED",
+            "test passes_after_a_failure ... ok",
+            "test fails_deep_in_the_stack ... FAILED",
+            "test result: FAILED. 2 passed; 2 failed",
+        ]
+    );
+    assert!(run.stderr.contains("---- fails ----"), "{}", run.stderr);
+    assert!(run.stderr.contains("arithmetic still works"), "{}", run.stderr);
+    assert!(run.stderr.contains("---- fails_deep_in_the_stack ----"), "{}", run.stderr);
+    assert!(run.stderr.contains("bottom reached"), "{}", run.stderr);
+}
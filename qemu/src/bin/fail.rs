@@ -0,0 +1,39 @@
+//! Fails on purpose, so the runner's own tests can see that a failed test fails the run without
+//! stopping the tests after it, even when it fails with a deep stack.
+
+#![no_main]
+#![no_std]
+
+use core::hint::black_box;
+
+use cortex_m_rt::entry;
+
+use beginstm_qemu::{harness, tests};
+
+#[entry]
+fn main() -> ! {
+    harness::run(tests![passes, fails, passes_after_a_failure, fails_deep_in_the_stack])
+}
+
+fn passes() {
+    assert_eq!(black_box(2) + 2, 4);
+}
+
+fn fails() {
+    assert_eq!(black_box(2) + 2, 5, "arithmetic still works");
+}
+
+fn passes_after_a_failure() {
+    passes();
+}
+
+fn fails_deep_in_the_stack() {
+    fn recurse(depth: u32) -> u32 {
+        let scratch = black_box([depth; 16]);
+        if depth == 0 {
+            panic!("bottom reached");
+        }
+        recurse(depth - 1) + scratch[0]
+    }
+    recurse(black_box(20));
+}
@@ -0,0 +1,79 @@
+//! The shared crate's integer and byte handling on real Thumb code, where the host's unit tests
+//! can't tell a 32-bit `usize`, alignment or wrapping mistake from correct code.
+
+#![no_main]
+#![no_std]
+
+use cortex_m_rt::entry;
+
+use beginstm_qemu::{harness, tests};
+use beginstm_shared::calendar::DateTime;
+use beginstm_shared::crc::crc16;
+use beginstm_shared::protocol::{Decoder, Encoder, Message, Status, Vector3, MAX_FRAME_LEN};
+use beginstm_shared::supervisor::Supervisor;
+use beginstm_shared::time::{Duration, Instant};
+use beginstm_shared::timer_wheel::Wheel;
+
+#[entry]
+fn main() -> ! {
+    harness::run(tests![crc, protocol_round_trip, calendar, timer_wheel, time_across_the_wrap])
+}
+
+fn crc() {
+    assert_eq!(crc16(b"123456789"), 0x29b1);
+}
+
+fn protocol_round_trip() {
+    let messages = [
+        Message::Accel(Vector3 { x: -1, y: 512, z: i16::MIN }),
+        Message::Status(Status { dropped: 7, sensor_errors: u32::MAX, time: 1_709_211_909 }),
+        Message::SetTime { unix: 0x0100_0000 },
+    ];
+    let mut encoder = Encoder::new();
+    let mut decoder = Decoder::new();
+    for (n, &message) in messages.iter().enumerate() {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let len = encoder.encode(message, 0xdead_beef, &mut frame).unwrap();
+        let mut decoded = None;
+        for &byte in &frame[..len] {
+            decoded = decoder.push(byte).or(decoded);
+        }
+        let packet = decoded.unwrap().unwrap();
+        assert_eq!(packet.seq, n as u16);
+        assert_eq!(packet.timestamp_ms, 0xdead_beef);
+        assert_eq!(packet.message, message);
+    }
+    assert_eq!(decoder.lost(), 0);
+}
+
+fn calendar() {
+    let date = DateTime::from_unix(4_102_444_799);
+    assert_eq!((date.year, date.month, date.day), (2099, 12, 31));
+    assert_eq!(date.to_unix(), 4_102_444_799);
+    assert_eq!(DateTime::from_unix(u32::MAX).year, 2106);
+}
+
+fn timer_wheel() {
+    let mut wheel: Wheel<u8, 4, 8> = Wheel::new();
+    wheel.every(3, 1).unwrap();
+    wheel.once(20, 2).unwrap();
+    let mut fired = 0;
+    for _ in 0..21 {
+        wheel.advance(1);
+        while let Some((_, payload)) = wheel.poll() {
+            fired += payload as u32;
+        }
+    }
+    assert_eq!(fired, 7 + 2); // Seven periods of 3 in 21 ticks, and the one-shot.
+}
+
+fn time_across_the_wrap() {
+    let start = Instant::from_micros(u32::MAX - 10);
+    let mut supervisor = Supervisor::new([Duration::from_micros(100)], start);
+    supervisor.check_in(0, start + Duration::from_micros(50));
+    assert_eq!(supervisor.overdue(start + Duration::from_micros(150)), None);
+    assert_eq!(
+        supervisor.overdue(start + Duration::from_micros(200)),
+        Some((0, Duration::from_micros(50)))
+    );
+}
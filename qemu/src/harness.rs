@@ -0,0 +1,107 @@
+//! A small stand-in for `#[test]` on the target, where libtest isn't available.
+//!
+//! `tests!` lists the test functions and `run` calls them in turn, printing a line per test and
+//! a summary over semihosting in the same format as `cargo test`, then exits with status 1 if
+//! any failed.  A test fails by panicking.  There's no unwinding, so the panic handler hands
+//! the panic to `failed`, which drops the failed test's stack frames by moving the stack
+//! pointer back to where `run` was called, and carries on with the next test.  Whatever the
+//! failed test left half-done, such as a `RefCell` still borrowed, stays that way, so tests
+//! shouldn't share state.  Panics in interrupt handlers aren't caught.
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use cortex_m_semihosting::{hio, hprint, hprintln};
+
+/// A test function and the name to report it under.
+pub struct Test {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+/// The tests to `run`, each named after its function.
+#[macro_export]
+macro_rules! tests {
+    ($($test:path),* $(,)?) => {
+        &[$($crate::harness::Test { name: stringify!($test), run: $test }),*]
+    };
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+// A `&'static [Test]`, kept as its parts for the panic handler to find.
+static TESTS: AtomicUsize = AtomicUsize::new(0);
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+/// Stack pointer to go back to after a failure.
+static STACK: AtomicU32 = AtomicU32::new(0);
+
+/// Run `tests`, report and exit QEMU.
+pub fn run(tests: &'static [Test]) -> ! {
+    TESTS.store(tests.as_ptr() as usize, Ordering::Relaxed);
+    COUNT.store(tests.len(), Ordering::Relaxed);
+    STACK.store(cortex_m::register::msp::read(), Ordering::Relaxed);
+    RUNNING.store(true, Ordering::Relaxed);
+    hprintln!("\nrunning {} tests", tests.len()).ok();
+    run_from(0)
+}
+
+fn tests() -> &'static [Test] {
+    // NOTE(unsafe) put together from the parts of the slice `run` was given.
+    unsafe { core::slice::from_raw_parts(TESTS.load(Ordering::Relaxed) as *const Test, COUNT.load(Ordering::Relaxed)) }
+}
+
+fn run_from(first: usize) -> ! {
+    let tests = tests();
+    for (n, test) in tests.iter().enumerate().skip(first) {
+        CURRENT.store(n, Ordering::Relaxed);
+        hprint!("test {} ... ", test.name).ok();
+        (test.run)();
+        hprintln!("ok").ok();
+    }
+
+    let failed = FAILED.load(Ordering::Relaxed);
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    hprintln!("\ntest result: {}. {} passed; {} failed", result, tests.len() - failed, failed).ok();
+    crate::exit(failed == 0)
+}
+
+/// Called by the panic handler.  Returns if no tests are running; otherwise reports the
+/// failure and goes on with the next test, so never returns.
+pub fn failed(info: &PanicInfo) {
+    if !RUNNING.load(Ordering::Relaxed) {
+        return;
+    }
+    hprintln!("FAILED").ok();
+    if let Ok(mut stderr) = hio::hstderr() {
+        let name = tests()[CURRENT.load(Ordering::Relaxed)].name;
+        writeln!(stderr, "---- {} ----\n{}", name, info).ok();
+    }
+    FAILED.fetch_add(1, Ordering::Relaxed);
+    // Interrupts were disabled for the panic; the next test starts like the first.
+    unsafe { cortex_m::interrupt::enable() };
+    next_test()
+}
+
+#[cfg(target_arch = "arm")]
+fn next_test() -> ! {
+    extern "C" fn resume() -> ! {
+        run_from(CURRENT.load(Ordering::Relaxed) + 1)
+    }
+    // NOTE(unsafe) everything on the stack below `run`'s frame belonged to the failed test.
+    unsafe {
+        core::arch::asm!(
+            "msr MSP, {stack}",
+            "b.w {resume}",
+            stack = in(reg) STACK.load(Ordering::Relaxed),
+            resume = sym resume,
+            options(noreturn),
+        )
+    }
+}
+
+#[cfg(not(target_arch = "arm"))]
+fn next_test() -> ! {
+    unreachable!("the harness only runs on the target")
+}
@@ -0,0 +1,38 @@
+//! Support for the programs in `src/bin`, which run under QEMU with semihosting.
+//!
+//! Each program prints what it checks to stdout and ends with `exit`, which becomes QEMU's
+//! exit status.  Most are lists of unit tests for `harness::run`, which carries on past a
+//! failed test.  Outside the harness, a panic prints its message to stderr and exits with a
+//! failure.  `runner/` builds the programs, runs them and checks the output and status; see the
+//! README for how.
+//!
+//! Nothing here is specific to QEMU but `memory.x` and the runner in `.cargo/config.toml`, so
+//! with the board's instead the same programs run on the board under a debugger.
+
+#![no_std]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use cortex_m_semihosting::{debug, hio};
+
+pub mod harness;
+
+/// Stop QEMU, with status 0 if `success`, else 1.
+pub fn exit(success: bool) -> ! {
+    debug::exit(if success { debug::EXIT_SUCCESS } else { debug::EXIT_FAILURE });
+    // Only reached without a debugger or QEMU to exit to.
+    loop {
+        cortex_m::asm::bkpt();
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    harness::failed(info);
+    if let Ok(mut stderr) = hio::hstderr() {
+        writeln!(stderr, "{}", info).ok();
+    }
+    exit(false)
+}
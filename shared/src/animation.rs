@@ -0,0 +1,286 @@
+//! LED animation waveforms and brightness correction.
+//!
+//! A [`Pattern`] says how bright a channel is (0 to [`LEVEL_MAX`], on a perceptual scale) at any
+//! time after the pattern started.  [`gamma`] turns a perceptual level into a linear duty
+//! fraction (0 to [`DUTY_MAX`]) because the eye's response to LED current is far from linear:
+//! without it a fade spends most of its time looking fully on.  [`Animator`] runs one pattern
+//! per channel and hands back the duties; the firmware scales them to the timer's reload value.
+
+/// Full brightness on the perceptual scale.
+pub const LEVEL_MAX: u8 = 255;
+/// Full duty after gamma correction.
+pub const DUTY_MAX: u16 = u16::MAX;
+
+/// Perceptual level to duty, using the CIE 1931 lightness curve, which is close to a gamma of
+/// 2.4 but has a linear toe so the dimmest steps aren't lost.
+static GAMMA: [u16; 256] = gamma_table();
+
+const fn gamma_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        // Lightness L* from 0 to 100, in thousandths.
+        let l = i as u128 * 100_000 / 255;
+        let y = if l <= 8_000 {
+            (l * DUTY_MAX as u128 + 451_650) / 903_300
+        } else {
+            let t = l + 16_000;
+            let cube = 116_000u128 * 116_000 * 116_000;
+            (t * t * t * DUTY_MAX as u128 + cube / 2) / cube
+        };
+        table[i] = y as u16;
+        i += 1;
+    }
+    table
+}
+
+/// Duty (0 to `DUTY_MAX`) that looks like `level` (0 to `LEVEL_MAX`).
+pub fn gamma(level: u8) -> u16 {
+    GAMMA[level as usize]
+}
+
+/// Scale a duty from 0..=`DUTY_MAX` to 0..=`max`, e.g. a timer's auto-reload value.
+pub fn scale(duty: u16, max: u16) -> u16 {
+    ((duty as u32 * max as u32 + DUTY_MAX as u32 / 2) / DUTY_MAX as u32) as u16
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    Off,
+    /// Constant level.
+    Solid(u8),
+    /// Smooth rise and fall from off to full and back, once per period.
+    Breathe { period_ms: u32 },
+    /// Straight line from one level to another, then hold the last one.
+    Fade { from: u8, to: u8, duration_ms: u32 },
+    /// Jump to full at the start of every period and die away over `width_ms`.
+    Pulse { period_ms: u32, width_ms: u32 },
+    /// Full brightness steps from channel to channel, once round all of them per period,
+    /// leaving a half-bright, fading tail on the channel it just left.
+    Chase { period_ms: u32 },
+}
+
+impl Pattern {
+    /// Level of `channel` (of `channels` taking part) `t_ms` after the pattern started.
+    pub fn level(&self, t_ms: u32, channel: usize, channels: usize) -> u8 {
+        match *self {
+            Pattern::Off => 0,
+            Pattern::Solid(level) => level,
+            Pattern::Breathe { period_ms } => {
+                // Triangle from 0 up to 1 and back, in 16.16 fixed point, then smoothstep
+                // (3x² - 2x³) so it lingers at the ends like breathing does.
+                let p = phase(t_ms, period_ms);
+                let x = if p < 0x8000 { p * 2 } else { (0x1_0000 - p) * 2 } as u64;
+                let s = (x * x * (3 * 0x1_0000 - 2 * x)) >> 32;
+                to_level(s as u32)
+            }
+            Pattern::Fade { from, to, duration_ms } => {
+                if t_ms >= duration_ms {
+                    return to;
+                }
+                let span = to as i32 - from as i32;
+                (from as i32 + (span as i64 * t_ms as i64 / duration_ms as i64) as i32) as u8
+            }
+            Pattern::Pulse { period_ms, width_ms } => {
+                let t = t_ms % period_ms.max(1);
+                if t >= width_ms {
+                    return 0;
+                }
+                LEVEL_MAX - (LEVEL_MAX as u32 * t / width_ms) as u8
+            }
+            Pattern::Chase { period_ms } => {
+                // Position of the head in 1/256ths of a channel.
+                let n = channels.max(1) as u32;
+                let head = ((phase(t_ms, period_ms) as u64 * n as u64 * 256) >> 16) as u32;
+                let behind = (head + n * 256 - (channel as u32 % n) * 256) % (n * 256);
+                match behind {
+                    0..=255 => LEVEL_MAX,
+                    256..=511 => ((511 - behind) / 2) as u8,
+                    _ => 0,
+                }
+            }
+        }
+    }
+}
+
+/// How far `t_ms` is into the current period, from 0 to 0xffff.
+fn phase(t_ms: u32, period_ms: u32) -> u32 {
+    let period_ms = period_ms.max(1);
+    ((t_ms % period_ms) as u64 * 0x1_0000 / period_ms as u64) as u32
+}
+
+/// 0 to 0x10000 fixed point to a level.
+fn to_level(x: u32) -> u8 {
+    ((x.min(0x1_0000) * LEVEL_MAX as u32 + 0x8000) >> 16) as u8
+}
+
+/// Runs one pattern on each of `N` channels.  Time only moves when [`Animator::advance`] is
+/// called, normally from a timer interrupt, so it doesn't need a clock of its own.
+pub struct Animator<const N: usize> {
+    patterns: [Pattern; N],
+    started_ms: [u32; N],
+    now_ms: u32,
+    brightness: u8,
+}
+
+impl<const N: usize> Animator<N> {
+    pub const fn new() -> Self {
+        Animator {
+            patterns: [Pattern::Off; N],
+            started_ms: [0; N],
+            now_ms: 0,
+            brightness: LEVEL_MAX,
+        }
+    }
+
+    /// Start `pattern` on one channel.  Out of range channels are ignored.
+    pub fn set(&mut self, channel: usize, pattern: Pattern) {
+        if channel < N {
+            self.patterns[channel] = pattern;
+            self.started_ms[channel] = self.now_ms;
+        }
+    }
+
+    /// Start `pattern` on every channel at once, which is what a chase needs.
+    pub fn set_all(&mut self, pattern: Pattern) {
+        for channel in 0..N {
+            self.set(channel, pattern);
+        }
+    }
+
+    pub fn pattern(&self, channel: usize) -> Option<Pattern> {
+        self.patterns.get(channel).copied()
+    }
+
+    /// Overall level that every pattern is scaled by, before gamma correction.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    /// Move time on by `dt_ms` and return the duty (0 to `DUTY_MAX`) for each channel.
+    pub fn advance(&mut self, dt_ms: u32) -> [u16; N] {
+        self.now_ms = self.now_ms.wrapping_add(dt_ms);
+        let mut duties = [0; N];
+        for (channel, duty) in duties.iter_mut().enumerate() {
+            let t = self.now_ms.wrapping_sub(self.started_ms[channel]);
+            let level = self.patterns[channel].level(t, channel, N) as u32;
+            *duty = gamma(((level * self.brightness as u32 + 127) / 255) as u8);
+        }
+        duties
+    }
+}
+
+impl<const N: usize> Default for Animator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gamma_curve() {
+        assert_eq!(gamma(0), 0);
+        assert_eq!(gamma(LEVEL_MAX), DUTY_MAX);
+        assert!(GAMMA.windows(2).all(|w| w[0] <= w[1]));
+        // Half brightness to the eye is under a fifth of the power.
+        assert!(gamma(128) < DUTY_MAX / 5);
+        assert!(gamma(1) > 0);
+    }
+
+    #[test]
+    fn scaling() {
+        assert_eq!(scale(DUTY_MAX, 4800), 4800);
+        assert_eq!(scale(0, 4800), 0);
+        assert_eq!(scale(DUTY_MAX / 2, 1000), 500);
+    }
+
+    #[test]
+    fn breathe() {
+        let p = Pattern::Breathe { period_ms: 2000 };
+        assert_eq!(p.level(0, 0, 1), 0);
+        assert_eq!(p.level(1000, 0, 1), LEVEL_MAX);
+        assert_eq!(p.level(2000, 0, 1), 0);
+        // Symmetric and rising through the first half.
+        for t in 0..1000 {
+            assert!((p.level(t, 0, 1) as i32 - p.level(2000 - t, 0, 1) as i32).abs() <= 1);
+            assert!(p.level(t, 0, 1) <= p.level(t + 1, 0, 1));
+        }
+        // Smoothstep lingers near the ends.
+        assert!(p.level(100, 0, 1) < 10);
+        assert_eq!(p.level(500, 0, 1), 128);
+    }
+
+    #[test]
+    fn fade() {
+        let up = Pattern::Fade { from: 0, to: 200, duration_ms: 1000 };
+        assert_eq!(up.level(0, 0, 1), 0);
+        assert_eq!(up.level(500, 0, 1), 100);
+        assert_eq!(up.level(1000, 0, 1), 200);
+        assert_eq!(up.level(60_000, 0, 1), 200);
+        let down = Pattern::Fade { from: 255, to: 55, duration_ms: 100 };
+        assert_eq!(down.level(50, 0, 1), 155);
+        let instant = Pattern::Fade { from: 0, to: 9, duration_ms: 0 };
+        assert_eq!(instant.level(0, 0, 1), 9);
+    }
+
+    #[test]
+    fn pulse() {
+        let p = Pattern::Pulse { period_ms: 1000, width_ms: 100 };
+        assert_eq!(p.level(0, 0, 1), LEVEL_MAX);
+        assert_eq!(p.level(50, 0, 1), 128);
+        assert_eq!(p.level(100, 0, 1), 0);
+        assert_eq!(p.level(999, 0, 1), 0);
+        assert_eq!(p.level(1000, 0, 1), LEVEL_MAX);
+    }
+
+    #[test]
+    fn chase() {
+        let p = Pattern::Chase { period_ms: 800 };
+        let levels = |t| [0, 1, 2, 3].map(|c| p.level(t, c, 4));
+        assert_eq!(levels(0), [255, 0, 0, 127]);
+        assert_eq!(levels(100), [255, 0, 0, 63]);
+        assert_eq!(levels(200), [127, 255, 0, 0]);
+        assert_eq!(levels(600), [0, 0, 127, 255]);
+        assert_eq!(levels(800), levels(0));
+        // Exactly one channel is at full at any time.
+        for t in 0..800 {
+            assert_eq!(levels(t).iter().filter(|&&l| l == LEVEL_MAX).count(), 1);
+        }
+    }
+
+    #[test]
+    fn zero_periods_dont_panic() {
+        for p in &[
+            Pattern::Breathe { period_ms: 0 },
+            Pattern::Pulse { period_ms: 0, width_ms: 0 },
+            Pattern::Chase { period_ms: 0 },
+        ] {
+            p.level(123, 0, 0);
+        }
+    }
+
+    #[test]
+    fn animator() {
+        let mut a = Animator::<2>::new();
+        assert_eq!(a.advance(10), [0, 0]);
+
+        a.set(0, Pattern::Solid(LEVEL_MAX));
+        a.set(1, Pattern::Fade { from: 0, to: LEVEL_MAX, duration_ms: 100 });
+        assert_eq!(a.advance(0), [DUTY_MAX, 0]);
+        assert_eq!(a.advance(100), [DUTY_MAX, DUTY_MAX]);
+        a.set(5, Pattern::Off);
+        assert_eq!(a.pattern(5), None);
+
+        a.set_brightness(128);
+        assert_eq!(a.advance(10), [gamma(128), gamma(128)]);
+
+        // Patterns keep going across the wrap of the millisecond count.
+        a.now_ms = u32::MAX - 5;
+        a.set_all(Pattern::Pulse { period_ms: 1000, width_ms: 100 });
+        a.set_brightness(LEVEL_MAX);
+        assert_eq!(a.advance(50), [gamma(128); 2]);
+    }
+}
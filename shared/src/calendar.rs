@@ -0,0 +1,185 @@
+//! Calendar dates and times, and their conversion to and from Unix time.
+//!
+//! The firmware keeps the date in the RTC's BCD calendar registers; the protocol and the host
+//! use Unix seconds (UTC, no leap seconds), which are easier to do arithmetic on.  The RTC only
+//! counts years 2000 to 2099, so that is the range `DateTime::is_valid` accepts.
+
+use core::fmt;
+
+/// Unix time of 2000-01-01 00:00:00, the RTC's earliest.
+pub const RTC_EPOCH: u32 = 946_684_800;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The date and time `unix` seconds after 1970-01-01 00:00:00.
+    pub fn from_unix(unix: u32) -> Self {
+        let days = (unix / 86_400) as i64;
+        let secs = unix % 86_400;
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year: year as u16,
+            month,
+            day,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00:00.  Only meaningful if the date is valid.
+    pub fn to_unix(&self) -> u32 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        (days * 86_400) as u32 + self.hour as u32 * 3600 + self.minute as u32 * 60 + self.second as u32
+    }
+
+    /// True if this is a real date and time that the RTC can hold.
+    pub fn is_valid(&self) -> bool {
+        (2000..=2099).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Day of the week, 1 for Monday to 7 for Sunday, as the RTC counts them.
+    pub fn weekday(&self) -> u8 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        // 1970-01-01 was a Thursday.
+        ((days + 3).rem_euclid(7) + 1) as u8
+    }
+}
+
+/// ISO 8601 style, `2024-02-29 13:05:09`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Two decimal digits, 0 to 99, as binary-coded decimal.
+pub const fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+pub const fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0x0f)
+}
+
+// Day counts from 1970-01-01 in the proleptic Gregorian calendar, after Howard Hinnant's
+// "chrono-compatible low-level date algorithms".  Years are shifted to start in March, so the
+// leap day comes last.
+
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 } as u8;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dt(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime { year, month, day, hour, minute, second }
+    }
+
+    #[test]
+    fn unix_conversions() {
+        let cases = [
+            (0, dt(1970, 1, 1, 0, 0, 0)),
+            (RTC_EPOCH, dt(2000, 1, 1, 0, 0, 0)),
+            (951_782_400, dt(2000, 2, 29, 0, 0, 0)),
+            (1_709_211_909, dt(2024, 2, 29, 13, 5, 9)),
+            (4_102_444_799, dt(2099, 12, 31, 23, 59, 59)),
+            (u32::MAX, dt(2106, 2, 7, 6, 28, 15)),
+        ];
+        for &(unix, date) in cases.iter() {
+            assert_eq!(DateTime::from_unix(unix), date, "{}", unix);
+            assert_eq!(date.to_unix(), unix, "{}", date);
+        }
+        // Every day of a leap year and the next, round trip.
+        for day in 0..731 {
+            let unix = 1_704_067_200 + day * 86_400 + 43_210; // From 2024-01-01.
+            assert_eq!(DateTime::from_unix(unix).to_unix(), unix);
+        }
+    }
+
+    #[test]
+    fn validity() {
+        assert!(dt(2000, 1, 1, 0, 0, 0).is_valid());
+        assert!(dt(2024, 2, 29, 23, 59, 59).is_valid());
+        assert!(!dt(2023, 2, 29, 0, 0, 0).is_valid());
+        assert!(dt(2000, 2, 29, 0, 0, 0).is_valid()); // Divisible by 400.
+        assert!(!dt(2100, 1, 1, 0, 0, 0).is_valid()); // Past the RTC's century.
+        assert!(!dt(1999, 12, 31, 0, 0, 0).is_valid());
+        assert!(!dt(2024, 4, 31, 0, 0, 0).is_valid());
+        assert!(!dt(2024, 13, 1, 0, 0, 0).is_valid());
+        assert!(!dt(2024, 1, 0, 0, 0, 0).is_valid());
+        assert!(!dt(2024, 1, 1, 24, 0, 0).is_valid());
+        assert!(!dt(2024, 1, 1, 0, 60, 0).is_valid());
+    }
+
+    #[test]
+    fn weekdays() {
+        assert_eq!(dt(2000, 1, 1, 0, 0, 0).weekday(), 6); // Saturday
+        assert_eq!(dt(1970, 1, 1, 0, 0, 0).weekday(), 4); // Thursday
+        assert_eq!(dt(2024, 2, 29, 0, 0, 0).weekday(), 4);
+        assert_eq!(dt(2026, 10, 19, 0, 0, 0).weekday(), 1); // Monday
+        assert_eq!(dt(2026, 10, 25, 0, 0, 0).weekday(), 7); // Sunday
+    }
+
+    #[test]
+    fn bcd_and_display() {
+        assert_eq!(to_bcd(59), 0x59);
+        assert_eq!(from_bcd(0x59), 59);
+        assert!((0..100).all(|n| from_bcd(to_bcd(n)) == n));
+        assert_eq!(dt(2024, 2, 9, 3, 5, 9).to_string(), "2024-02-09 03:05:09");
+    }
+}
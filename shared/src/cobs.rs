@@ -0,0 +1,132 @@
+//! Consistent Overhead Byte Stuffing.
+//!
+//! COBS removes every zero byte from a packet at a cost of at most one byte per 254,
+//! which leaves zero free to mark the end of each frame on the wire.  A receiver that
+//! joins mid-stream, or loses bytes, resynchronises at the next zero.
+
+/// Worst-case encoded length of `len` bytes, not counting the frame delimiter.
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The destination buffer is too small.
+    BufferTooSmall,
+    /// The input contains a zero byte or a code that runs past the end.
+    Malformed,
+}
+
+/// Encode `src` into `dst`.  Returns the encoded length; no delimiter is appended.
+pub fn encode(src: &[u8], dst: &mut [u8]) -> Result<usize, Error> {
+    if dst.len() < max_encoded_len(src.len()) {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let mut code_idx = 0; // Where the current block's code byte goes.
+    let mut out = 1;
+    let mut code = 1u8;
+
+    for &byte in src {
+        if byte == 0 {
+            dst[code_idx] = code;
+            code_idx = out;
+            out += 1;
+            code = 1;
+        } else {
+            dst[out] = byte;
+            out += 1;
+            code += 1;
+            if code == 0xff {
+                dst[code_idx] = code;
+                code_idx = out;
+                out += 1;
+                code = 1;
+            }
+        }
+    }
+    dst[code_idx] = code;
+
+    Ok(out)
+}
+
+/// Decode `src` (without its delimiter) into `dst`.  Returns the decoded length.
+pub fn decode(src: &[u8], dst: &mut [u8]) -> Result<usize, Error> {
+    let mut i = 0;
+    let mut out = 0;
+
+    while i < src.len() {
+        let code = src[i] as usize;
+        if code == 0 || i + code > src.len() {
+            return Err(Error::Malformed);
+        }
+        i += 1;
+
+        for &byte in &src[i..i + code - 1] {
+            if byte == 0 {
+                return Err(Error::Malformed);
+            }
+            *dst.get_mut(out).ok_or(Error::BufferTooSmall)? = byte;
+            out += 1;
+        }
+        i += code - 1;
+
+        // A block shorter than 254 bytes implies a zero after it, except at the very end.
+        if code < 0xff && i < src.len() {
+            *dst.get_mut(out).ok_or(Error::BufferTooSmall)? = 0;
+            out += 1;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![0; max_encoded_len(data.len())];
+        let len = encode(data, &mut encoded).unwrap();
+        encoded.truncate(len);
+        assert!(!encoded.contains(&0));
+
+        let mut decoded = vec![0; data.len()];
+        let len = decode(&encoded, &mut decoded).unwrap();
+        assert_eq!(&decoded[..len], data);
+        encoded
+    }
+
+    #[test]
+    fn known_vectors() {
+        // Examples from the COBS paper and Wikipedia.
+        assert_eq!(round_trip(&[]), [0x01]);
+        assert_eq!(round_trip(&[0x00]), [0x01, 0x01]);
+        assert_eq!(round_trip(&[0x00, 0x00]), [0x01, 0x01, 0x01]);
+        assert_eq!(round_trip(&[0x11, 0x22, 0x00, 0x33]), [0x03, 0x11, 0x22, 0x02, 0x33]);
+        assert_eq!(round_trip(&[0x11, 0x00, 0x00, 0x00]), [0x02, 0x11, 0x01, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn long_runs() {
+        let data: Vec<u8> = (1..=254).collect();
+        let encoded = round_trip(&data);
+        assert_eq!(encoded[0], 0xff);
+        assert_eq!(encoded.len(), 256);
+
+        let data: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+        round_trip(&data);
+        let data = vec![0xaa; 600];
+        round_trip(&data);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let mut out = [0; 16];
+        assert_eq!(decode(&[0x03, 0x11], &mut out), Err(Error::Malformed));
+        assert_eq!(decode(&[0x03, 0x11, 0x00], &mut out), Err(Error::Malformed));
+        assert_eq!(decode(&[0x00], &mut out), Err(Error::Malformed));
+        assert_eq!(encode(&[1, 2, 3], &mut out[..3]), Err(Error::BufferTooSmall));
+        assert_eq!(decode(&[0x04, 1, 2, 3], &mut out[..2]), Err(Error::BufferTooSmall));
+    }
+}
@@ -0,0 +1,226 @@
+//! What the firmware saves about a crash for the next boot to report.
+//!
+//! The record lives in RAM that the runtime doesn't initialise, so it survives the reset the
+//! crash handlers end with, but after a power cycle that RAM holds noise.  A magic number and a
+//! CRC over the contents tell a record from the noise, and `seal` sets both once the record is
+//! complete, so a crash while it was being written leaves nothing that looks valid.
+
+use core::fmt;
+
+use crate::crc;
+use crate::fault;
+
+/// Longest panic message kept; the rest is cut off.
+pub const MESSAGE_LEN: usize = 96;
+
+const MAGIC: u32 = 0xC8A5_4ED0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    HardFault,
+    Panic,
+    MemManage,
+    BusFault,
+    UsageFault,
+}
+
+const KINDS: [Kind; 5] = [Kind::HardFault, Kind::Panic, Kind::MemManage, Kind::BusFault, Kind::UsageFault];
+
+/// The registers the core stacks on exception entry, in stacking order.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+/// Plain integers throughout, since whatever is in RAM at boot must be a value of this type.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    magic: u32,
+    kind: u32,
+    /// Where a fault happened.  All zero for a panic.
+    pub frame: Frame,
+    /// Fault status and address registers from the SCB, zero for a panic.
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    message_len: u32,
+    message: [u8; MESSAGE_LEN],
+    crc: u32,
+}
+
+impl CrashRecord {
+    /// An empty record of `kind`, to fill in and then `seal`.
+    pub fn new(kind: Kind) -> Self {
+        CrashRecord {
+            magic: 0,
+            kind: kind as u32,
+            frame: Frame::default(),
+            cfsr: 0,
+            hfsr: 0,
+            mmfar: 0,
+            bfar: 0,
+            message_len: 0,
+            message: [0; MESSAGE_LEN],
+            crc: 0,
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        KINDS.get(self.kind as usize).copied().unwrap_or(Kind::HardFault)
+    }
+
+    /// The panic message and location, as much as fitted.
+    pub fn message(&self) -> &str {
+        let len = (self.message_len as usize).min(MESSAGE_LEN);
+        // Only whole characters are ever written, but a record from noise is checked first.
+        core::str::from_utf8(&self.message[..len]).unwrap_or("")
+    }
+
+    /// Mark the record complete.
+    pub fn seal(&mut self) {
+        self.magic = MAGIC;
+        self.crc = self.checksum() as u32;
+    }
+
+    /// Mark the record as used, so the next boot ignores it.
+    pub fn clear(&mut self) {
+        self.magic = 0;
+    }
+
+    /// True if this is a sealed record rather than RAM noise or a cleared one.
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.crc == self.checksum() as u32 && (self.kind as usize) < KINDS.len()
+    }
+
+    fn checksum(&self) -> u16 {
+        let f = &self.frame;
+        let words = [
+            self.magic, self.kind, f.r0, f.r1, f.r2, f.r3, f.r12, f.lr, f.pc, f.xpsr,
+            self.cfsr, self.hfsr, self.mmfar, self.bfar, self.message_len,
+        ];
+        let crc = words.iter().fold(0xFFFF, |crc, word| crc::update(crc, &word.to_le_bytes()));
+        crc::update(crc, &self.message)
+    }
+}
+
+/// Appends to the message, dropping whatever doesn't fit.
+impl fmt::Write for CrashRecord {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.message_len as usize;
+        let mut end = (start + s.len()).min(MESSAGE_LEN);
+        while !s.is_char_boundary(end - start) {
+            end -= 1;
+        }
+        self.message[start..end].copy_from_slice(&s.as_bytes()[..end - start]);
+        self.message_len = end as u32;
+        Ok(())
+    }
+}
+
+/// A few lines for the log.
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind() {
+            Kind::Panic => write!(f, "Last reset by a panic: {}", self.message()),
+            kind => {
+                let r = &self.frame;
+                writeln!(f, "Last reset by a {:?} at PC {:#010x}, LR {:#010x}", kind, r.pc, r.lr)?;
+                f.write_str("  Cause:")?;
+                for (n, cause) in fault::causes(self.cfsr, self.hfsr).enumerate() {
+                    write!(f, "{} {}", if n == 0 { "" } else { "," }, cause)?;
+                }
+                match fault::address(self.cfsr, self.mmfar, self.bfar) {
+                    Some(address) => writeln!(f, ", at address {:#010x}", address)?,
+                    None => writeln!(f)?,
+                }
+                writeln!(f, "  r0 {:#010x}  r1 {:#010x}  r2 {:#010x}  r3 {:#010x}", r.r0, r.r1, r.r2, r.r3)?;
+                writeln!(f, "  r12 {:#010x}  xpsr {:#010x}", r.r12, r.xpsr)?;
+                write!(
+                    f,
+                    "  CFSR {:#010x}  HFSR {:#010x}  MMFAR {:#010x}  BFAR {:#010x}",
+                    self.cfsr, self.hfsr, self.mmfar, self.bfar
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn sealed_records_are_valid_until_changed_or_cleared() {
+        let mut record = CrashRecord::new(Kind::HardFault);
+        record.frame.pc = 0x0800_0ab6;
+        record.cfsr = 0x0000_8200;
+        assert!(!record.is_valid());
+        record.seal();
+        assert!(record.is_valid());
+        assert_eq!(record.kind(), Kind::HardFault);
+
+        let mut corrupted = record;
+        corrupted.bfar ^= 1;
+        assert!(!corrupted.is_valid());
+        record.clear();
+        assert!(!record.is_valid());
+    }
+
+    #[test]
+    fn noise_is_not_a_record() {
+        let mut record = CrashRecord::new(Kind::Panic);
+        record.kind = 7;
+        record.seal();
+        assert!(!record.is_valid());
+        record.kind = Kind::UsageFault as u32;
+        record.seal();
+        assert!(record.is_valid());
+        record = CrashRecord::new(Kind::Panic);
+        record.message = [0xff; MESSAGE_LEN];
+        record.message_len = 1000;
+        assert_eq!(record.message(), "");
+    }
+
+    #[test]
+    fn long_messages_are_cut_on_a_character_boundary() {
+        let mut record = CrashRecord::new(Kind::Panic);
+        write!(record, "panicked at src/main.rs:123:5: ").unwrap();
+        for _ in 0..MESSAGE_LEN {
+            record.write_str("é").unwrap();
+        }
+        assert_eq!(record.message().len(), MESSAGE_LEN - 1); // 31 bytes, then 32 two-byte characters.
+        assert!(record.message().ends_with('é'));
+        record.seal();
+        assert!(record.is_valid());
+        assert!(record.to_string().starts_with("Last reset by a panic: panicked at src/main.rs:123:5: é"));
+    }
+
+    #[test]
+    fn faults_are_reported_with_their_causes() {
+        let mut record = CrashRecord::new(Kind::BusFault);
+        record.frame.pc = 0x0800_0ab6;
+        record.frame.lr = 0x0800_0435;
+        record.cfsr = 0x0000_8200;
+        record.bfar = 0x2fff_ffff;
+        let report = record.to_string();
+        let mut lines = report.lines();
+        assert_eq!(lines.next(), Some("Last reset by a BusFault at PC 0x08000ab6, LR 0x08000435"));
+        assert_eq!(lines.next(), Some("  Cause: precise bus error, at address 0x2fffffff"));
+
+        record.cfsr = 0x0000_0400;
+        record.hfsr = 0x4000_0000;
+        let report = record.to_string();
+        assert_eq!(report.lines().nth(1), Some("  Cause: imprecise bus error, forced escalation"));
+    }
+}
@@ -0,0 +1,45 @@
+//! CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF, no reflection, no final XOR).
+//!
+//! Bitwise rather than table-driven: frames are short and 512 bytes of flash for a table
+//! isn't worth it at these data rates.
+
+const POLY: u16 = 0x1021;
+const INIT: u16 = 0xffff;
+
+/// CRC of `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    update(INIT, data)
+}
+
+/// Continue a CRC over more data, for input that arrives in pieces.
+pub fn update(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ POLY
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        // The standard check input for every CRC catalogue entry.
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn incremental() {
+        let crc = update(crc16(b"1234"), b"56789");
+        assert_eq!(crc, crc16(b"123456789"));
+    }
+}
@@ -0,0 +1,149 @@
+//! Decoding of the Cortex-M4 fault status registers into readable causes.
+//!
+//! CFSR holds three registers in one: MMFSR (bits 0 to 7) for MemManage faults, BFSR (8 to 15)
+//! for bus faults and UFSR (16 to 31) for usage faults.  HFSR says why a HardFault happened,
+//! which is mostly "forced": a configurable fault that was disabled, or that happened while its
+//! own handler couldn't run, escalated.  MMFAR and BFAR hold the address that faulted, but only
+//! while the matching valid bit in CFSR is set; an imprecise bus error has no address at all,
+//! and its stacked PC is somewhere after the store that caused it.
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cause {
+    /// Instruction fetch from a region the MPU forbids, or that can't execute.
+    InstructionAccess,
+    /// Data access to a region the MPU forbids, such as the stack guard.
+    DataAccess,
+    MemUnstacking,
+    /// Stacking for an exception hit the MPU, which usually means the stack overflowed.
+    MemStacking,
+    MemLazyFp,
+    InstructionBus,
+    /// A bus error whose address is in BFAR.
+    PreciseBus,
+    /// A bus error on a buffered write, found after the instruction had moved on.
+    ImpreciseBus,
+    BusUnstacking,
+    BusStacking,
+    BusLazyFp,
+    UndefinedInstruction,
+    /// Branch to an address without the Thumb bit, usually a bad function pointer.
+    InvalidState,
+    InvalidPc,
+    NoCoprocessor,
+    Unaligned,
+    DivideByZero,
+    /// Reading the vector table failed.
+    VectorTable,
+    /// A configurable fault escalated to HardFault.
+    Forced,
+    DebugEvent,
+}
+
+/// CFSR bits, with HFSR's after them at `HFSR_SHIFT`, paired with their causes.
+const BITS: [(u8, Cause); 20] = [
+    (0, Cause::InstructionAccess),
+    (1, Cause::DataAccess),
+    (3, Cause::MemUnstacking),
+    (4, Cause::MemStacking),
+    (5, Cause::MemLazyFp),
+    (8, Cause::InstructionBus),
+    (9, Cause::PreciseBus),
+    (10, Cause::ImpreciseBus),
+    (11, Cause::BusUnstacking),
+    (12, Cause::BusStacking),
+    (13, Cause::BusLazyFp),
+    (16, Cause::UndefinedInstruction),
+    (17, Cause::InvalidState),
+    (18, Cause::InvalidPc),
+    (19, Cause::NoCoprocessor),
+    (24, Cause::Unaligned),
+    (25, Cause::DivideByZero),
+    (HFSR_SHIFT + 1, Cause::VectorTable),
+    (HFSR_SHIFT + 30, Cause::Forced),
+    (HFSR_SHIFT + 31, Cause::DebugEvent),
+];
+const HFSR_SHIFT: u8 = 32;
+
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+
+/// The causes set in `cfsr` and `hfsr`, configurable faults first.
+pub fn causes(cfsr: u32, hfsr: u32) -> impl Iterator<Item = Cause> {
+    let bits = ((hfsr as u64) << HFSR_SHIFT) | cfsr as u64;
+    BITS.iter().filter(move |&&(bit, _)| bits & (1 << bit) != 0).map(|&(_, cause)| cause)
+}
+
+/// The address that faulted, if the registers hold one: the data address of a MemManage
+/// fault, or failing that that of a precise bus error.
+pub fn address(cfsr: u32, mmfar: u32, bfar: u32) -> Option<u32> {
+    if cfsr & MMARVALID != 0 {
+        Some(mmfar)
+    } else if cfsr & BFARVALID != 0 {
+        Some(bfar)
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Cause::InstructionAccess => "instruction access violation",
+            Cause::DataAccess => "data access violation",
+            Cause::MemUnstacking => "MPU fault unstacking",
+            Cause::MemStacking => "MPU fault stacking",
+            Cause::MemLazyFp => "MPU fault saving FPU state",
+            Cause::InstructionBus => "instruction bus error",
+            Cause::PreciseBus => "precise bus error",
+            Cause::ImpreciseBus => "imprecise bus error",
+            Cause::BusUnstacking => "bus error unstacking",
+            Cause::BusStacking => "bus error stacking",
+            Cause::BusLazyFp => "bus error saving FPU state",
+            Cause::UndefinedInstruction => "undefined instruction",
+            Cause::InvalidState => "invalid state",
+            Cause::InvalidPc => "invalid PC on exception return",
+            Cause::NoCoprocessor => "no coprocessor",
+            Cause::Unaligned => "unaligned access",
+            Cause::DivideByZero => "divide by zero",
+            Cause::VectorTable => "vector table read",
+            Cause::Forced => "forced escalation",
+            Cause::DebugEvent => "debug event",
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn list(cfsr: u32, hfsr: u32) -> Vec<Cause> {
+        causes(cfsr, hfsr).collect()
+    }
+
+    #[test]
+    fn decodes_each_register() {
+        assert_eq!(list(0, 0), []);
+        assert_eq!(list(1 << 25, 0), [Cause::DivideByZero]);
+        assert_eq!(list(1 << 17, 1 << 30), [Cause::InvalidState, Cause::Forced]);
+        assert_eq!(list(0x0000_0400, 0x4000_0000), [Cause::ImpreciseBus, Cause::Forced]);
+        assert_eq!(list(0x0000_0012, 0), [Cause::DataAccess, Cause::MemStacking]);
+        assert_eq!(list(1 << 24, 1 << 1), [Cause::Unaligned, Cause::VectorTable]);
+        // The valid and reserved bits aren't causes.
+        assert_eq!(list(MMARVALID | BFARVALID | 1 << 2 | 1 << 20, 1), []);
+    }
+
+    #[test]
+    fn addresses_only_when_valid() {
+        assert_eq!(address(0x0000_0400, 0xE000_ED34, 0xE000_ED38), None);
+        assert_eq!(address(0x0000_8200, 0xE000_ED34, 0x2fff_ffff), Some(0x2fff_ffff));
+        assert_eq!(address(0x0000_0082, 0x2000_0000, 0xE000_ED38), Some(0x2000_0000));
+    }
+
+    #[test]
+    fn descriptions() {
+        assert_eq!(Cause::ImpreciseBus.to_string(), "imprecise bus error");
+        assert_eq!(Cause::Forced.to_string(), "forced escalation");
+    }
+}
@@ -0,0 +1,90 @@
+//! What the firmware's heap reports about itself.
+//!
+//! A long-running program that allocates and frees blocks of different sizes can end up with
+//! plenty of free memory in total but none of it in one piece.  `fragmentation` puts a number
+//! on that, from the free total and the largest block that would still succeed.
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// The heap's size in bytes.
+    pub size: usize,
+    /// Bytes handed out, including the allocator's padding.
+    pub used: usize,
+    /// The most `used` has been.
+    pub peak: usize,
+    /// The largest block that can be allocated now.
+    pub largest_free: usize,
+    /// Allocations that succeeded and that failed, since the start.
+    pub allocations: u32,
+    pub failures: u32,
+}
+
+impl Stats {
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+
+    /// How much of the free memory is out of reach of a single allocation, in percent: 0 if
+    /// it's all in one block, approaching 100 as it is split into ever smaller pieces.
+    pub fn fragmentation(&self) -> u32 {
+        match self.free() {
+            0 => 0,
+            free => (100 - self.largest_free.min(free) as u64 * 100 / free as u64) as u32,
+        }
+    }
+
+    /// Note an allocation, or a failed one, that left `used` bytes in use.
+    pub fn allocated(&mut self, used: usize, succeeded: bool) {
+        if succeeded {
+            self.allocations = self.allocations.wrapping_add(1);
+        } else {
+            self.failures = self.failures.wrapping_add(1);
+        }
+        self.used = used;
+        self.peak = self.peak.max(used);
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes used, {} at most; largest free block {} bytes, {}% fragmented; {} allocations, {} failed",
+            self.used,
+            self.size,
+            self.peak,
+            self.largest_free,
+            self.fragmentation(),
+            self.allocations,
+            self.failures
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn peak_and_counts() {
+        let mut stats = Stats { size: 1024, ..Stats::default() };
+        stats.allocated(100, true);
+        stats.allocated(300, true);
+        stats.allocated(300, false);
+        stats.allocated(40, true);
+        assert_eq!((stats.used, stats.peak, stats.free()), (40, 300, 984));
+        assert_eq!((stats.allocations, stats.failures), (3, 1));
+    }
+
+    #[test]
+    fn fragmentation() {
+        let stats = |used, largest_free| Stats { size: 1000, used, largest_free, ..Stats::default() };
+        assert_eq!(stats(200, 800).fragmentation(), 0);
+        assert_eq!(stats(200, 400).fragmentation(), 50);
+        assert_eq!(stats(200, 8).fragmentation(), 99);
+        assert_eq!(stats(200, 0).fragmentation(), 100);
+        assert_eq!(stats(1000, 0).fragmentation(), 0);
+    }
+}
@@ -0,0 +1,183 @@
+//! The steps of an I2C master transfer on the STM32F3's I2C peripheral, when DMA moves the
+//! bytes and interrupts report progress.
+//!
+//! A transfer writes some bytes, reads some, or writes and then reads after a repeated start,
+//! the usual way to read a device's registers.  Each phase is one write of CR2 (the address,
+//! direction and byte count) with the DMA channel for that direction set up beforehand; then
+//! the CPU hears nothing until the phase ends.  A write that a read follows ends with the TC
+//! (transfer complete) event, and the read is started from there; the last phase ends with an
+//! automatic STOP.  A NACK also ends in a STOP, with the NACKF flag set.
+//!
+//! `Transfer` says what to do at the start and after each event, and the firmware's driver
+//! (firmware/src/i2c_dma.rs) does it.
+
+/// Most bytes a phase can move without the RELOAD mechanism, which isn't used.
+pub const MAX_LEN: usize = 255;
+
+const CR2_RD_WRN: u32 = 1 << 10;
+const CR2_START: u32 = 1 << 13;
+const CR2_NBYTES_SHIFT: u32 = 16;
+const CR2_AUTOEND: u32 = 1 << 25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The device didn't acknowledge its address or a byte.
+    Nack,
+    /// A misplaced START or STOP, or a STOP before the transfer was done.
+    Bus,
+    /// Another master took the bus.
+    ArbitrationLost,
+    /// A transfer was still in progress.
+    Busy,
+    /// More bytes than the buffers or a phase can hold.
+    TooLong,
+}
+
+/// What the peripheral reported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// TC: the bytes of a phase without AUTOEND have gone.
+    TransferComplete,
+    /// STOPF, and whether NACKF was set with it.
+    Stop { nack: bool },
+    BusError,
+    ArbitrationLost,
+}
+
+/// What the driver does next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// Point the transmit DMA channel at `len` bytes, then write `cr2`.
+    Write { cr2: u32, len: usize },
+    /// Point the receive DMA channel at room for `len` bytes, then write `cr2`.
+    Read { cr2: u32, len: usize },
+    /// Nothing until the next event.
+    Wait,
+    /// The transfer is over.  After an error the peripheral needs resetting.
+    Done(Result<(), Error>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Writing,
+    Reading,
+    Done,
+}
+
+pub struct Transfer {
+    address: u8,
+    write_len: usize,
+    read_len: usize,
+    phase: Phase,
+}
+
+impl Transfer {
+    /// Write `write_len` bytes to the device at 7-bit `address`, then read `read_len`.  With
+    /// neither, just the address is sent, to see if anything answers.
+    pub fn new(address: u8, write_len: usize, read_len: usize) -> Result<Self, Error> {
+        if write_len > MAX_LEN || read_len > MAX_LEN {
+            return Err(Error::TooLong);
+        }
+        Ok(Transfer { address, write_len, read_len, phase: Phase::Done })
+    }
+
+    /// The first step.
+    pub fn start(&mut self) -> Step {
+        if self.write_len > 0 || self.read_len == 0 {
+            self.phase = Phase::Writing;
+            let autoend = self.read_len == 0;
+            Step::Write { cr2: cr2(self.address, self.write_len, false, autoend), len: self.write_len }
+        } else {
+            self.read()
+        }
+    }
+
+    /// The step after `event`.
+    pub fn on_event(&mut self, event: Event) -> Step {
+        let step = match (self.phase, event) {
+            (Phase::Done, _) => return Step::Wait,
+            (_, Event::BusError) => Step::Done(Err(Error::Bus)),
+            (_, Event::ArbitrationLost) => Step::Done(Err(Error::ArbitrationLost)),
+            (_, Event::Stop { nack: true }) => Step::Done(Err(Error::Nack)),
+            (Phase::Writing, Event::TransferComplete) if self.read_len > 0 => return self.read(),
+            (Phase::Writing, Event::Stop { nack: false }) if self.read_len == 0 => Step::Done(Ok(())),
+            (Phase::Reading, Event::Stop { nack: false }) => Step::Done(Ok(())),
+            (_, Event::TransferComplete) => return Step::Wait,
+            (_, Event::Stop { nack: false }) => Step::Done(Err(Error::Bus)),
+        };
+        self.phase = Phase::Done;
+        step
+    }
+
+    /// The transfer has ended, one way or the other.
+    pub fn is_done(&self) -> bool {
+        self.phase == Phase::Done
+    }
+
+    fn read(&mut self) -> Step {
+        self.phase = Phase::Reading;
+        Step::Read { cr2: cr2(self.address, self.read_len, true, true), len: self.read_len }
+    }
+}
+
+/// CR2 to start a phase of `len` bytes, generating a STOP at the end if `autoend`.
+fn cr2(address: u8, len: usize, read: bool, autoend: bool) -> u32 {
+    let mut cr2 = (address as u32) << 1 | CR2_START | (len as u32) << CR2_NBYTES_SHIFT;
+    if read {
+        cr2 |= CR2_RD_WRN;
+    }
+    if autoend {
+        cr2 |= CR2_AUTOEND;
+    }
+    cr2
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn register_read() {
+        let mut transfer = Transfer::new(0x19, 1, 6).unwrap();
+        assert_eq!(transfer.start(), Step::Write { cr2: 0x0001_2032, len: 1 });
+        assert!(!transfer.is_done());
+        assert_eq!(transfer.on_event(Event::TransferComplete), Step::Read { cr2: 0x0206_2432, len: 6 });
+        assert_eq!(transfer.on_event(Event::TransferComplete), Step::Wait);
+        assert_eq!(transfer.on_event(Event::Stop { nack: false }), Step::Done(Ok(())));
+        assert!(transfer.is_done());
+        assert_eq!(transfer.on_event(Event::Stop { nack: false }), Step::Wait);
+    }
+
+    #[test]
+    fn write_or_read_alone() {
+        let mut write = Transfer::new(0x1e, 2, 0).unwrap();
+        assert_eq!(write.start(), Step::Write { cr2: 0x0202_203c, len: 2 });
+        assert_eq!(write.on_event(Event::Stop { nack: false }), Step::Done(Ok(())));
+
+        let mut read = Transfer::new(0x1e, 0, 3).unwrap();
+        assert_eq!(read.start(), Step::Read { cr2: 0x0203_243c, len: 3 });
+        assert_eq!(read.on_event(Event::Stop { nack: false }), Step::Done(Ok(())));
+
+        let mut probe = Transfer::new(0x08, 0, 0).unwrap();
+        assert_eq!(probe.start(), Step::Write { cr2: 0x0200_2010, len: 0 });
+        assert_eq!(probe.on_event(Event::Stop { nack: true }), Step::Done(Err(Error::Nack)));
+    }
+
+    #[test]
+    fn failures() {
+        let mut transfer = Transfer::new(0x19, 1, 6).unwrap();
+        transfer.start();
+        assert_eq!(transfer.on_event(Event::Stop { nack: true }), Step::Done(Err(Error::Nack)));
+
+        transfer.start();
+        // A STOP with a read still to come.
+        assert_eq!(transfer.on_event(Event::Stop { nack: false }), Step::Done(Err(Error::Bus)));
+
+        transfer.start();
+        transfer.on_event(Event::TransferComplete);
+        assert_eq!(transfer.on_event(Event::ArbitrationLost), Step::Done(Err(Error::ArbitrationLost)));
+        assert!(transfer.is_done());
+
+        assert!(Transfer::new(0x19, 1, MAX_LEN + 1).is_err());
+    }
+}
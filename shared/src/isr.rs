@@ -0,0 +1,250 @@
+//! The hardware-facing part of the firmware's interrupt handlers, written against the traits
+//! in `regs` so that host tests can drive them with the register blocks in `sim`.
+//!
+//! The firmware's handlers find the registers and their state and call these.  What each must
+//! get right is mostly clearing its flag: one that isn't cleared fires again as soon as the
+//! handler returns, and the main loop never runs again.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::regs::{Exti, Gpio, Timer};
+use crate::spsc::Producer;
+
+/// The user button's EXTI line, PA0.
+pub const BUTTON_LINE: u32 = 0;
+
+/// EXTI0: clear the button's request and queue `press` for the main loop.  If the queue is
+/// full the press is dropped, and counted.
+pub fn button<T, const N: usize>(exti: &impl Exti, presses: &mut Producer<'_, T, N>, press: T) {
+    exti.clear_pending(1 << BUTTON_LINE);
+    presses.enqueue(press).ok();
+}
+
+/// A timer's update interrupt as a tick counter: clear the flag and count one.
+pub fn tick(tim: &impl Timer, ticks: &AtomicU32) {
+    tim.clear_update();
+    ticks.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Bit-angle modulation of up to 16 pins of a GPIO port, 8 bits of brightness each.
+///
+/// A frame shows the eight bits of every pin's level one after the other, bit k for `unit`
+/// << k timer ticks.  The timer's update interrupt calls `next_bit` at the end of each.
+///
+/// The timer needs ARR preload (ARPE) on.  Each bit's length is then loaded by the hardware at
+/// the update that starts it, from a value written a bit earlier, so a late interrupt only
+/// delays the switch of the pins and never leaves the counter past a shortened ARR, which would
+/// run it to 0xFFFF and round.  One more than a whole bit late, `unit` ticks at the shortest,
+/// runs two bits together for a frame.
+pub struct BitAngle {
+    /// The pins driven here.
+    pins: u16,
+    /// Timer ticks in the least significant bit.
+    unit: u16,
+    levels: [u8; 16],
+    /// For each bit k, the pins whose level has bit k set.
+    planes: [u16; 8],
+    /// The bit being shown.
+    bit: usize,
+}
+
+impl BitAngle {
+    /// Drive `pins`, all off to start with.  `unit << 7` must fit in the reload register.
+    pub fn new(pins: u16, unit: u16) -> Self {
+        BitAngle { pins, unit, levels: [0; 16], planes: [0; 8], bit: 0 }
+    }
+
+    /// The pins driven here.
+    pub fn pins(&self) -> u16 {
+        self.pins
+    }
+
+    /// Start the first frame on `tim`, which has ARR preload on and isn't counting yet.
+    pub fn start(&mut self, tim: &impl Timer) {
+        self.bit = 0;
+        tim.set_reload(self.period(0));
+        tim.force_update();
+        tim.clear_update();
+        tim.set_reload(self.period(1));
+    }
+
+    /// Set `pin`'s brightness, 0 (off) to 255 (fully on), from the next time each bit comes
+    /// round.  False if the pin isn't driven here.
+    pub fn set(&mut self, pin: u32, level: u8) -> bool {
+        if self.level(pin).is_none() {
+            return false;
+        }
+        self.levels[pin as usize] = level;
+        for (k, plane) in self.planes.iter_mut().enumerate() {
+            if level & (1 << k) != 0 {
+                *plane |= 1 << pin;
+            } else {
+                *plane &= !(1 << pin);
+            }
+        }
+        true
+    }
+
+    /// `pin`'s brightness, or None if the pin isn't driven here.
+    pub fn level(&self, pin: u32) -> Option<u8> {
+        (pin < 16 && self.pins & 1 << pin != 0).then(|| self.levels[pin as usize])
+    }
+
+    /// Start of a bit: clear the update flag, switch every pin for it with one write and set
+    /// how long the bit after it lasts.
+    pub fn next_bit(&mut self, tim: &impl Timer, gpio: &impl Gpio) {
+        tim.clear_update();
+        self.bit = (self.bit + 1) % self.planes.len();
+        let on = self.planes[self.bit];
+        let off = self.pins & !on;
+        gpio.set_reset(on as u32 | (off as u32) << 16);
+        // The length of this bit is in ARR already; this one is preloaded for the next update.
+        tim.set_reload(self.period((self.bit + 1) % self.planes.len()));
+    }
+
+    /// ARR for bit `bit`.
+    fn period(&self, bit: usize) -> u16 {
+        (self.unit << bit) - 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::{SimExti, SimGpio, SimTimer};
+    use crate::spsc::Queue;
+
+    #[test]
+    fn button_clears_only_its_own_line() {
+        let exti = SimExti::new();
+        let presses: Queue<u32, 2> = Queue::new();
+        let (mut producer, mut consumer) = (presses.producer().unwrap(), presses.consumer().unwrap());
+        exti.edge(BUTTON_LINE);
+        exti.edge(1);
+        button(&exti, &mut producer, 10);
+        assert!(!exti.requesting(BUTTON_LINE));
+        assert!(exti.requesting(1));
+
+        for press in 11..14 {
+            exti.edge(BUTTON_LINE);
+            button(&exti, &mut producer, press);
+            assert!(!exti.requesting(BUTTON_LINE));
+        }
+        assert_eq!((consumer.dequeue(), consumer.dequeue(), consumer.dequeue()), (Some(10), Some(11), None));
+        assert_eq!(consumer.dropped(), 2);
+    }
+
+    #[test]
+    fn ticks_count_each_update() {
+        let tim = SimTimer::new();
+        let ticks = AtomicU32::new(0);
+        for _ in 0..3 {
+            tim.overflow();
+            assert!(tim.update_pending());
+            tick(&tim, &ticks);
+            assert!(!tim.requesting());
+        }
+        assert_eq!(ticks.load(Ordering::Relaxed), 3);
+    }
+
+    /// A timer with preload on, started for `pwm`.
+    fn timer(pwm: &mut BitAngle) -> SimTimer {
+        let tim = SimTimer::new();
+        tim.enable_preload();
+        pwm.start(&tim);
+        assert!(!tim.requesting());
+        tim
+    }
+
+    /// Run for `frames` frames after one to settle, the interrupt handler answering each update
+    /// `late` ticks after it.  Returns the ticks each pin spent high and the most the counter
+    /// reached.
+    fn run(
+        pwm: &mut BitAngle,
+        tim: &SimTimer,
+        gpio: &SimGpio,
+        frames: u32,
+        late: u32,
+    ) -> ([u32; 16], u16) {
+        let frame = 255 * pwm.unit as u32;
+        let (mut high, mut most, mut waited) = ([0; 16], 0, 0);
+        for tick in 0..frame * (frames + 1) {
+            tim.tick();
+            if tim.requesting() {
+                if waited == late {
+                    pwm.next_bit(tim, gpio);
+                    waited = 0;
+                } else {
+                    waited += 1;
+                }
+            }
+            most = most.max(tim.count());
+            if tick >= frame {
+                for (pin, high) in high.iter_mut().enumerate() {
+                    if gpio.is_high(pin as u32) {
+                        *high += 1;
+                    }
+                }
+            }
+        }
+        (high, most)
+    }
+
+    #[test]
+    fn pins_are_high_for_their_level() {
+        let gpio = SimGpio::new();
+        let mut pwm = BitAngle::new(0xf000, 2);
+        assert!(pwm.set(12, 255));
+        assert!(pwm.set(13, 1));
+        assert!(pwm.set(14, 0b1010_0101));
+        assert!(!pwm.set(8, 255));
+        assert_eq!(pwm.level(8), None);
+        assert_eq!(pwm.level(14), Some(0b1010_0101));
+
+        let tim = timer(&mut pwm);
+        let (high, _) = run(&mut pwm, &tim, &gpio, 1, 0);
+        assert_eq!(high[12..], [255 * 2, 2, 0b1010_0101 * 2, 0]);
+        assert_eq!(high[..12], [0; 12]);
+    }
+
+    #[test]
+    fn other_pins_are_left_alone() {
+        let gpio = SimGpio::new();
+        gpio.set_reset(0x0101);
+        let mut pwm = BitAngle::new(0x0f00, 1);
+        pwm.set(8, 0);
+        pwm.set(9, 255);
+        let tim = timer(&mut pwm);
+        let (high, _) = run(&mut pwm, &tim, &gpio, 1, 0);
+        assert_eq!((high[0], high[8], high[9]), (255, 0, 255));
+        pwm.set(9, 0);
+        run(&mut pwm, &tim, &gpio, 1, 0);
+        assert_eq!(gpio.output(), 0x0001);
+    }
+
+    #[test]
+    fn late_interrupts_keep_the_brightness() {
+        let gpio = SimGpio::new();
+        let mut pwm = BitAngle::new(0x0300, 8);
+        pwm.set(8, 0b1000_0001);
+        pwm.set(9, 0b0111_1110);
+        let tim = timer(&mut pwm);
+        // Late on every bit, including the short bit 0 after the long bit 7.
+        let (high, most) = run(&mut pwm, &tim, &gpio, 3, 7);
+        assert_eq!((high[8], high[9]), (3 * 0b1000_0001 * 8, 3 * 0b0111_1110 * 8));
+        assert_eq!(most, (8 << 7) - 1);
+    }
+
+    #[test]
+    fn very_late_interrupts_never_run_the_counter_out() {
+        let gpio = SimGpio::new();
+        let mut pwm = BitAngle::new(0x0100, 2);
+        pwm.set(8, 0x80);
+        let tim = timer(&mut pwm);
+        // More than a whole bit 0 late: two bits run together, but the counter stays in range
+        // instead of running on to 0xFFFF.
+        let (high, most) = run(&mut pwm, &tim, &gpio, 4, 5);
+        assert!(most < 2 << 7, "counter reached {:#x}", most);
+        assert!(high[8] > 3 * 0x80 * 2, "high for {} ticks", high[8]);
+    }
+}
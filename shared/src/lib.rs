@@ -0,0 +1,29 @@
+//! Code shared between the beginstm firmware and the programs that run on the host.
+//!
+//! Everything here is `no_std` and free of hardware access, so it builds for the
+//! Cortex-M target and for the host, where `cargo test` exercises it.  Interrupt handlers reach
+//! their registers through the traits in `regs`, which `sim` implements for the tests.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod animation;
+pub mod calendar;
+pub mod cobs;
+pub mod crash;
+pub mod crc;
+pub mod fault;
+pub mod heap;
+pub mod i2c;
+pub mod isr;
+pub mod lsm303;
+pub mod pool;
+pub mod protocol;
+pub mod pwm;
+pub mod regs;
+pub mod ring;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+pub mod spsc;
+pub mod supervisor;
+pub mod time;
+pub mod timer_wheel;
@@ -0,0 +1,51 @@
+//! Where the LSM303AGR keeps its latest samples and how to decode them, for reading them
+//! without the driver crate.
+//!
+//! These match what the `lsm303agr` driver does for `accel_data` and `mag_data` with the
+//! settings the firmware uses, so a sample reads the same either way.
+
+use crate::protocol::Vector3;
+
+pub const ACCEL_ADDRESS: u8 = 0x19;
+pub const MAG_ADDRESS: u8 = 0x1e;
+/// OUT_X_L_A, with the top bit set to read the six output registers in one go.
+pub const ACCEL_OUT: u8 = 0x28 | AUTO_INCREMENT;
+/// OUTX_L_REG_M.  The magnetometer steps on by itself, but the driver sets the bit anyway.
+pub const MAG_OUT: u8 = 0x68 | AUTO_INCREMENT;
+const AUTO_INCREMENT: u8 = 0x80;
+/// Bytes of output registers to read.
+pub const OUT_LEN: usize = 6;
+
+/// In normal mode the accelerometer's 10 bits are at the top of each 16-bit register.
+const ACCEL_NORMAL_MODE_DIVISOR: i16 = 1 << 6;
+
+/// The accelerometer's output registers, read from `ACCEL_OUT`, in normal mode.
+pub fn accel(raw: &[u8; OUT_LEN]) -> Vector3 {
+    let [x, y, z] = words(raw).map(|word| word / ACCEL_NORMAL_MODE_DIVISOR);
+    Vector3 { x, y, z }
+}
+
+/// The magnetometer's output registers, read from `MAG_OUT`.
+pub fn mag(raw: &[u8; OUT_LEN]) -> Vector3 {
+    let [x, y, z] = words(raw);
+    Vector3 { x, y, z }
+}
+
+/// X, Y and Z, each low byte first.
+fn words(raw: &[u8; OUT_LEN]) -> [i16; 3] {
+    [0, 2, 4].map(|n| i16::from_le_bytes([raw[n], raw[n + 1]]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_little_endian_words() {
+        let raw = [0x40, 0x00, 0xc0, 0xff, 0x00, 0x80];
+        assert_eq!(mag(&raw), Vector3 { x: 0x40, y: -0x40, z: i16::MIN });
+        assert_eq!(accel(&raw), Vector3 { x: 1, y: -1, z: -512 });
+        // Rounds towards zero, like the driver.
+        assert_eq!(accel(&[0xff, 0xff, 0x3f, 0x00, 0xc1, 0xff]), Vector3 { x: 0, y: 0, z: 0 });
+    }
+}
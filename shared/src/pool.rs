@@ -0,0 +1,159 @@
+//! A fixed-block allocator: room for up to 32 values of one type, taken and given back in
+//! constant time without a lock.
+//!
+//! The heap's time to allocate depends on how fragmented it is, and it must be locked against
+//! interrupts while it searches, so it's no use to an interrupt handler that has to finish on
+//! time.  A `Pool` keeps a bit per slot in one atomic word instead; taking a slot is a
+//! compare-and-swap, retried only if an interrupt took or gave back a slot in between.  A
+//! `Block` gives its slot back when dropped, from whichever context that happens in.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub struct Pool<T, const N: usize> {
+    /// Bit n set if slot n is free.
+    free: AtomicU32,
+    slots: UnsafeCell<MaybeUninit<[T; N]>>,
+}
+
+// Each slot belongs to at most one `Block`, which may be sent to another context.
+unsafe impl<T: Send, const N: usize> Sync for Pool<T, N> {}
+
+impl<T, const N: usize> Pool<T, N> {
+    pub const fn new() -> Self {
+        const { assert!(N <= 32, "a pool has at most 32 slots") };
+        Pool {
+            free: AtomicU32::new(if N == 32 { u32::MAX } else { (1 << N) - 1 }),
+            slots: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Put `value` in a free slot, or hand it back if there is none.
+    pub fn alloc(&self, value: T) -> Result<Block<'_, T, N>, T> {
+        let mut free = self.free.load(Ordering::Relaxed);
+        loop {
+            if free == 0 {
+                return Err(value);
+            }
+            let slot = free.trailing_zeros() as usize;
+            match self.free.compare_exchange_weak(free, free & !(1 << slot), Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => {
+                    // NOTE(unsafe) the slot was free and is now this block's alone.
+                    unsafe { self.slot(slot).write(value) };
+                    return Ok(Block { pool: self, slot, _value: PhantomData });
+                }
+                Err(now) => free = now,
+            }
+        }
+    }
+
+    /// Free slots.
+    pub fn available(&self) -> usize {
+        self.free.load(Ordering::Relaxed).count_ones() as usize
+    }
+
+    fn slot(&self, slot: usize) -> *mut T {
+        // NOTE(unsafe) in bounds; `Block` makes sure only one pointer to a slot is in use.
+        unsafe { self.slots.get().cast::<T>().add(slot) }
+    }
+}
+
+impl<T, const N: usize> Default for Pool<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A value in a `Pool`'s slot.
+pub struct Block<'a, T, const N: usize> {
+    pool: &'a Pool<T, N>,
+    slot: usize,
+    /// Owns a `T`, so a `Block` is only `Sync` when `T` is: `&Block` hands out `&T`.
+    _value: PhantomData<T>,
+}
+
+impl<T, const N: usize> Deref for Block<'_, T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // NOTE(unsafe) `alloc` wrote the slot and only this block uses it.
+        unsafe { &*self.pool.slot(self.slot) }
+    }
+}
+
+impl<T, const N: usize> DerefMut for Block<'_, T, N> {
+    fn deref_mut(&mut self) -> &mut T {
+        // NOTE(unsafe) as for `deref`.
+        unsafe { &mut *self.pool.slot(self.slot) }
+    }
+}
+
+impl<T, const N: usize> Drop for Block<'_, T, N> {
+    fn drop(&mut self) {
+        // NOTE(unsafe) the value is dropped once, before the slot is marked free.
+        unsafe { self.pool.slot(self.slot).drop_in_place() };
+        self.pool.free.fetch_or(1 << self.slot, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn fills_up_and_reuses_slots() {
+        let pool: Pool<[u8; 16], 3> = Pool::new();
+        let a = pool.alloc([1; 16]).unwrap();
+        let mut b = pool.alloc([2; 16]).unwrap();
+        let c = pool.alloc([3; 16]).unwrap();
+        assert_eq!(pool.alloc([4; 16]).err(), Some([4; 16]));
+        assert_eq!(pool.available(), 0);
+
+        b[0] = 20;
+        assert_eq!((a[0], b[0], b[1], c[0]), (1, 20, 2, 3));
+        drop(b);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(*pool.alloc([5; 16]).unwrap(), [5; 16]);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn drops_values_once() {
+        let counted = Rc::new(());
+        let pool: Pool<Rc<()>, 32> = Pool::new();
+        let blocks: Vec<_> = (0..32).map(|_| pool.alloc(counted.clone()).unwrap()).collect();
+        assert_eq!(Rc::strong_count(&counted), 33);
+        drop(blocks);
+        assert_eq!(Rc::strong_count(&counted), 1);
+        assert_eq!(pool.available(), 32);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let pool: Arc<Pool<u64, 8>> = Arc::new(Pool::new());
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    for n in 0..10_000 {
+                        let value = t * 1_000_000 + n;
+                        if let Ok(mut block) = pool.alloc(value) {
+                            *block += 1;
+                            assert_eq!(*block, value + 1);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(pool.available(), 8);
+    }
+}
@@ -0,0 +1,780 @@
+//! Binary telemetry and command protocol between the board and the host.
+//!
+//! Every packet on the wire is laid out as
+//!
+//! ``` text
+//! +------+----------+--------------+-----------------+----------+
+//! | type | sequence | timestamp ms | payload         | CRC-16   |
+//! | u8   | u16 LE   | u32 LE       | depends on type | u16 LE   |
+//! +------+----------+--------------+-----------------+----------+
+//! ```
+//!
+//! then COBS encoded and terminated with a zero byte.  The CRC (see [`crate::crc`]) covers
+//! everything before it.  The sequence number increments by one for every packet the sender
+//! emits, so the receiver can count what it lost; the timestamp is milliseconds since the
+//! sender booted.
+//!
+//! Payloads, all little-endian:
+//!
+//! | type | message | payload                                     |
+//! |------|---------|---------------------------------------------|
+//! | 0x01 | Accel   | x, y, z: i16 (raw LSM303AGR counts)         |
+//! | 0x02 | Mag     | x, y, z: i16 (raw LSM303AGR counts)         |
+//! | 0x03 | Button  | pressed: u8                                 |
+//! | 0x04 | Status  | dropped: u32, sensor_errors: u32, time: u32 |
+//! | 0x05 | Log     | UTF-8 text, up to `MAX_TEXT_LEN` bytes      |
+//!
+//! Commands, host to board, each answered by one of the replies below:
+//!
+//! | type | message       | payload                                   | reply      |
+//! |------|---------------|-------------------------------------------|------------|
+//! | 0x10 | ScanI2c       | none                                      | I2cDevices |
+//! | 0x11 | SetLed        | led: u8 (0 = PE8 .. 7 = PE15), on: u8     | Ack/Nack   |
+//! | 0x12 | SetSampleRate | hz: u16                                   | Ack/Nack   |
+//! | 0x13 | ReadConfig    | none                                      | Config     |
+//! | 0x14 | SetTime       | unix: u32                                 | Ack/Nack   |
+//! | 0x15 | ReadTime      | none                                      | Time       |
+//!
+//! Replies, board to host:
+//!
+//! | type | message    | payload                                       |
+//! |------|------------|-----------------------------------------------|
+//! | 0x20 | I2cDevices | 16 bytes, bit n set if address n answered     |
+//! | 0x21 | Config     | sample_rate_hz: u16, leds: u8 (bit n = LED n) |
+//! | 0x22 | Ack        | seq: u16 of the command                       |
+//! | 0x23 | Nack       | seq: u16 of the command, reason: u8           |
+//! | 0x24 | Time       | unix: u32, 0 if the board's clock isn't set   |
+//!
+//! Times are Unix seconds, UTC.  A `Status` carries the board's date and time as of its own
+//! timestamp, which lets the host put a date and time on every other packet.
+
+use core::fmt;
+
+use crate::cobs;
+use crate::crc::crc16;
+
+/// Type tag + sequence number + timestamp.
+pub const HEADER_LEN: usize = 7;
+pub const CRC_LEN: usize = 2;
+/// Longest text a `Log` message carries; longer strings are truncated.
+pub const MAX_TEXT_LEN: usize = 96;
+pub const MAX_PAYLOAD_LEN: usize = MAX_TEXT_LEN;
+/// Largest packet before COBS encoding.
+pub const MAX_PACKET_LEN: usize = HEADER_LEN + MAX_PAYLOAD_LEN + CRC_LEN;
+/// Largest frame on the wire, including the zero delimiter.
+pub const MAX_FRAME_LEN: usize = cobs::max_encoded_len(MAX_PACKET_LEN) + 1;
+
+mod tag {
+    pub const ACCEL: u8 = 0x01;
+    pub const MAG: u8 = 0x02;
+    pub const BUTTON: u8 = 0x03;
+    pub const STATUS: u8 = 0x04;
+    pub const LOG: u8 = 0x05;
+
+    pub const SCAN_I2C: u8 = 0x10;
+    pub const SET_LED: u8 = 0x11;
+    pub const SET_SAMPLE_RATE: u8 = 0x12;
+    pub const READ_CONFIG: u8 = 0x13;
+    pub const SET_TIME: u8 = 0x14;
+    pub const READ_TIME: u8 = 0x15;
+
+    pub const I2C_DEVICES: u8 = 0x20;
+    pub const CONFIG: u8 = 0x21;
+    pub const ACK: u8 = 0x22;
+    pub const NACK: u8 = 0x23;
+    pub const TIME: u8 = 0x24;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer can't hold the frame.
+    BufferTooSmall,
+    /// The frame isn't valid COBS, or is longer than `MAX_FRAME_LEN`.
+    Framing,
+    /// The CRC doesn't match the contents.
+    Crc,
+    /// The type tag isn't one this version knows about.
+    UnknownType(u8),
+    /// The payload is the wrong length for its type, or the text isn't UTF-8.
+    Payload,
+}
+
+impl From<cobs::Error> for Error {
+    fn from(e: cobs::Error) -> Self {
+        match e {
+            cobs::Error::BufferTooSmall => Error::BufferTooSmall,
+            cobs::Error::Malformed => Error::Framing,
+        }
+    }
+}
+
+/// Three-axis sensor reading.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Vector3 {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+/// Counters the board reports periodically.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Status {
+    /// Bytes the board couldn't send because the link was backed up.
+    pub dropped: u32,
+    /// Failed sensor reads.
+    pub sensor_errors: u32,
+    /// The board's date and time in Unix seconds, or 0 if its clock isn't set.
+    pub time: u32,
+}
+
+/// Which 7-bit I2C addresses answered a scan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct I2cMap(pub [u8; 16]);
+
+impl I2cMap {
+    pub fn insert(&mut self, addr: u8) {
+        self.0[(addr as usize >> 3) & 0x0f] |= 1 << (addr & 7);
+    }
+
+    pub fn contains(&self, addr: u8) -> bool {
+        addr < 0x80 && self.0[addr as usize >> 3] & (1 << (addr & 7)) != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..0x80).filter(move |&addr| self.contains(addr))
+    }
+}
+
+/// Board settings, as reported in reply to `ReadConfig`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    pub sample_rate_hz: u16,
+    /// Bit n set if LED n (PE8 + n) is on.
+    pub leds: u8,
+}
+
+/// Why the board refused a command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NackReason {
+    /// An argument is out of range.
+    BadArgument,
+    /// The thing the command addresses is in use by something else.
+    Busy,
+    /// The board doesn't implement the command.
+    Unsupported,
+    /// A reason newer than this version of the protocol.
+    Other(u8),
+}
+
+impl From<u8> for NackReason {
+    fn from(code: u8) -> Self {
+        match code {
+            1 => NackReason::BadArgument,
+            2 => NackReason::Busy,
+            3 => NackReason::Unsupported,
+            other => NackReason::Other(other),
+        }
+    }
+}
+
+impl From<NackReason> for u8 {
+    fn from(reason: NackReason) -> u8 {
+        match reason {
+            NackReason::BadArgument => 1,
+            NackReason::Busy => 2,
+            NackReason::Unsupported => 3,
+            NackReason::Other(code) => code,
+        }
+    }
+}
+
+/// Fixed-capacity UTF-8 string, so log messages don't need an allocator.
+#[derive(Clone, Copy)]
+pub struct Text {
+    buf: [u8; MAX_TEXT_LEN],
+    len: u8,
+}
+
+impl Text {
+    pub const fn new() -> Self {
+        Text {
+            buf: [0; MAX_TEXT_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever filled from &str, cut on char boundaries, or validated on decode.
+        core::str::from_utf8(&self.buf[..self.len as usize]).unwrap_or("")
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len as usize == MAX_TEXT_LEN
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() > MAX_TEXT_LEN || core::str::from_utf8(bytes).is_err() {
+            return Err(Error::Payload);
+        }
+        let mut text = Text::new();
+        text.buf[..bytes.len()].copy_from_slice(bytes);
+        text.len = bytes.len() as u8;
+        Ok(text)
+    }
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Text::new()
+    }
+}
+
+impl From<&str> for Text {
+    /// Copies as much of `s` as fits.
+    fn from(s: &str) -> Self {
+        let mut text = Text::new();
+        fmt::Write::write_str(&mut text, s).ok();
+        text
+    }
+}
+
+impl fmt::Write for Text {
+    /// Appends as much of `s` as fits, stopping at a character boundary.  Never fails, so a
+    /// long `write!` produces a truncated message rather than nothing.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MAX_TEXT_LEN - self.len as usize;
+        let mut take = s.len().min(room);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+        let start = self.len as usize;
+        self.buf[start..start + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take as u8;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for Text {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Text {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    Accel(Vector3),
+    Mag(Vector3),
+    Button { pressed: bool },
+    Status(Status),
+    Log(Text),
+
+    ScanI2c,
+    SetLed { led: u8, on: bool },
+    SetSampleRate { hz: u16 },
+    ReadConfig,
+    SetTime { unix: u32 },
+    ReadTime,
+
+    I2cDevices(I2cMap),
+    Config(Config),
+    Ack { seq: u16 },
+    Nack { seq: u16, reason: NackReason },
+    Time { unix: u32 },
+}
+
+impl Message {
+    fn tag(&self) -> u8 {
+        match self {
+            Message::Accel(_) => tag::ACCEL,
+            Message::Mag(_) => tag::MAG,
+            Message::Button { .. } => tag::BUTTON,
+            Message::Status(_) => tag::STATUS,
+            Message::Log(_) => tag::LOG,
+            Message::ScanI2c => tag::SCAN_I2C,
+            Message::SetLed { .. } => tag::SET_LED,
+            Message::SetSampleRate { .. } => tag::SET_SAMPLE_RATE,
+            Message::ReadConfig => tag::READ_CONFIG,
+            Message::SetTime { .. } => tag::SET_TIME,
+            Message::ReadTime => tag::READ_TIME,
+            Message::I2cDevices(_) => tag::I2C_DEVICES,
+            Message::Config(_) => tag::CONFIG,
+            Message::Ack { .. } => tag::ACK,
+            Message::Nack { .. } => tag::NACK,
+            Message::Time { .. } => tag::TIME,
+        }
+    }
+
+    /// True for the messages the board sends in answer to a command.
+    pub fn is_reply(&self) -> bool {
+        matches!(
+            self,
+            Message::I2cDevices(_)
+                | Message::Config(_)
+                | Message::Ack { .. }
+                | Message::Nack { .. }
+                | Message::Time { .. }
+        )
+    }
+
+    /// Write the payload into `buf`, which is at least `MAX_PAYLOAD_LEN` long.
+    fn write_payload(&self, buf: &mut [u8]) -> usize {
+        match self {
+            Message::Accel(v) | Message::Mag(v) => {
+                buf[0..2].copy_from_slice(&v.x.to_le_bytes());
+                buf[2..4].copy_from_slice(&v.y.to_le_bytes());
+                buf[4..6].copy_from_slice(&v.z.to_le_bytes());
+                6
+            }
+            Message::Button { pressed } => {
+                buf[0] = *pressed as u8;
+                1
+            }
+            Message::Status(s) => {
+                buf[0..4].copy_from_slice(&s.dropped.to_le_bytes());
+                buf[4..8].copy_from_slice(&s.sensor_errors.to_le_bytes());
+                buf[8..12].copy_from_slice(&s.time.to_le_bytes());
+                12
+            }
+            Message::Log(text) => {
+                let bytes = text.as_str().as_bytes();
+                buf[..bytes.len()].copy_from_slice(bytes);
+                bytes.len()
+            }
+            Message::ScanI2c | Message::ReadConfig | Message::ReadTime => 0,
+            Message::SetLed { led, on } => {
+                buf[0] = *led;
+                buf[1] = *on as u8;
+                2
+            }
+            Message::SetSampleRate { hz } => {
+                buf[0..2].copy_from_slice(&hz.to_le_bytes());
+                2
+            }
+            Message::SetTime { unix } | Message::Time { unix } => {
+                buf[0..4].copy_from_slice(&unix.to_le_bytes());
+                4
+            }
+            Message::I2cDevices(map) => {
+                buf[0..16].copy_from_slice(&map.0);
+                16
+            }
+            Message::Config(c) => {
+                buf[0..2].copy_from_slice(&c.sample_rate_hz.to_le_bytes());
+                buf[2] = c.leds;
+                3
+            }
+            Message::Ack { seq } => {
+                buf[0..2].copy_from_slice(&seq.to_le_bytes());
+                2
+            }
+            Message::Nack { seq, reason } => {
+                buf[0..2].copy_from_slice(&seq.to_le_bytes());
+                buf[2] = (*reason).into();
+                3
+            }
+        }
+    }
+
+    fn read_payload(tag: u8, buf: &[u8]) -> Result<Self, Error> {
+        let expect = |len: usize| if buf.len() == len { Ok(()) } else { Err(Error::Payload) };
+        let i16_at = |i: usize| i16::from_le_bytes([buf[i], buf[i + 1]]);
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+
+        match tag {
+            tag::ACCEL | tag::MAG => {
+                expect(6)?;
+                let v = Vector3 {
+                    x: i16_at(0),
+                    y: i16_at(2),
+                    z: i16_at(4),
+                };
+                Ok(if tag == tag::ACCEL {
+                    Message::Accel(v)
+                } else {
+                    Message::Mag(v)
+                })
+            }
+            tag::BUTTON => {
+                expect(1)?;
+                Ok(Message::Button { pressed: buf[0] != 0 })
+            }
+            tag::STATUS => {
+                expect(12)?;
+                Ok(Message::Status(Status {
+                    dropped: u32_at(0),
+                    sensor_errors: u32_at(4),
+                    time: u32_at(8),
+                }))
+            }
+            tag::LOG => Ok(Message::Log(Text::from_bytes(buf)?)),
+            tag::SCAN_I2C => expect(0).map(|_| Message::ScanI2c),
+            tag::SET_LED => {
+                expect(2)?;
+                Ok(Message::SetLed { led: buf[0], on: buf[1] != 0 })
+            }
+            tag::SET_SAMPLE_RATE => {
+                expect(2)?;
+                Ok(Message::SetSampleRate { hz: u16_at(0) })
+            }
+            tag::READ_CONFIG => expect(0).map(|_| Message::ReadConfig),
+            tag::SET_TIME => {
+                expect(4)?;
+                Ok(Message::SetTime { unix: u32_at(0) })
+            }
+            tag::READ_TIME => expect(0).map(|_| Message::ReadTime),
+            tag::I2C_DEVICES => {
+                expect(16)?;
+                let mut map = I2cMap::default();
+                map.0.copy_from_slice(buf);
+                Ok(Message::I2cDevices(map))
+            }
+            tag::CONFIG => {
+                expect(3)?;
+                Ok(Message::Config(Config {
+                    sample_rate_hz: u16_at(0),
+                    leds: buf[2],
+                }))
+            }
+            tag::ACK => {
+                expect(2)?;
+                Ok(Message::Ack { seq: u16_at(0) })
+            }
+            tag::NACK => {
+                expect(3)?;
+                Ok(Message::Nack {
+                    seq: u16_at(0),
+                    reason: buf[2].into(),
+                })
+            }
+            tag::TIME => {
+                expect(4)?;
+                Ok(Message::Time { unix: u32_at(0) })
+            }
+            other => Err(Error::UnknownType(other)),
+        }
+    }
+}
+
+/// A message with its sequence number and timestamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub seq: u16,
+    pub timestamp_ms: u32,
+    pub message: Message,
+}
+
+impl Packet {
+    /// Encode into a complete frame, zero delimiter included.  Returns the frame length.
+    /// `buf` should be `MAX_FRAME_LEN` long to be sure of fitting any message.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut raw = [0u8; MAX_PACKET_LEN];
+        raw[0] = self.message.tag();
+        raw[1..3].copy_from_slice(&self.seq.to_le_bytes());
+        raw[3..7].copy_from_slice(&self.timestamp_ms.to_le_bytes());
+        let mut len = HEADER_LEN + self.message.write_payload(&mut raw[HEADER_LEN..]);
+        let crc = crc16(&raw[..len]);
+        raw[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        len += CRC_LEN;
+
+        let encoded = cobs::encode(&raw[..len], buf)?;
+        *buf.get_mut(encoded).ok_or(Error::BufferTooSmall)? = 0;
+        Ok(encoded + 1)
+    }
+
+    /// Decode one frame, with or without its trailing zero delimiter.
+    pub fn decode(frame: &[u8]) -> Result<Packet, Error> {
+        let frame = match frame.split_last() {
+            Some((0, rest)) => rest,
+            _ => frame,
+        };
+        let mut raw = [0u8; MAX_PACKET_LEN];
+        let len = cobs::decode(frame, &mut raw).map_err(|_| Error::Framing)?;
+        if len < HEADER_LEN + CRC_LEN {
+            return Err(Error::Framing);
+        }
+
+        let (body, crc) = raw[..len].split_at(len - CRC_LEN);
+        if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+            return Err(Error::Crc);
+        }
+
+        Ok(Packet {
+            seq: u16::from_le_bytes([body[1], body[2]]),
+            timestamp_ms: u32::from_le_bytes([body[3], body[4], body[5], body[6]]),
+            message: Message::read_payload(body[0], &body[HEADER_LEN..])?,
+        })
+    }
+}
+
+/// Numbers and timestamps outgoing messages, for the sending side.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    seq: u16,
+}
+
+impl Encoder {
+    pub const fn new() -> Self {
+        Encoder { seq: 0 }
+    }
+
+    /// The sequence number the next packet will get.
+    pub fn next_seq(&self) -> u16 {
+        self.seq
+    }
+
+    /// Frame `message` into `buf` with the next sequence number.  Returns the frame length.
+    pub fn encode(&mut self, message: Message, timestamp_ms: u32, buf: &mut [u8]) -> Result<usize, Error> {
+        let packet = Packet {
+            seq: self.seq,
+            timestamp_ms,
+            message,
+        };
+        let len = packet.encode(buf)?;
+        self.seq = self.seq.wrapping_add(1);
+        Ok(len)
+    }
+}
+
+/// Splits a byte stream into frames and decodes them, for the receiving side.
+///
+/// Also tracks sequence numbers, so the receiver can report how many packets went missing.
+pub struct Decoder {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+    overflow: bool,
+    next_seq: Option<u16>,
+    lost: u32,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+            overflow: false,
+            next_seq: None,
+            lost: 0,
+        }
+    }
+
+    /// Feed one byte.  Returns a result each time a frame delimiter arrives.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Packet, Error>> {
+        if byte != 0 {
+            if self.len < self.buf.len() {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+
+        let result = if self.overflow {
+            Err(Error::Framing)
+        } else if self.len == 0 {
+            // Back-to-back delimiters; senders may use them to flush a receiver.
+            return None;
+        } else {
+            Packet::decode(&self.buf[..self.len])
+        };
+        self.len = 0;
+        self.overflow = false;
+
+        if let Ok(ref packet) = result {
+            if let Some(expected) = self.next_seq {
+                // A jump backwards means the sender restarted, not that we lost 65000 packets.
+                let gap = packet.seq.wrapping_sub(expected);
+                if gap < 0x8000 {
+                    self.lost += gap as u32;
+                }
+            }
+            self.next_seq = Some(packet.seq.wrapping_add(1));
+        }
+        Some(result)
+    }
+
+    /// Packets skipped according to the sequence numbers seen so far.
+    pub fn lost(&self) -> u32 {
+        self.lost
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::fmt::Write;
+
+    fn sample_messages() -> Vec<Message> {
+        let mut long = Text::new();
+        for _ in 0..20 {
+            write!(long, "héllo ").unwrap();
+        }
+        assert!(long.as_str().len() <= MAX_TEXT_LEN);
+
+        let mut map = I2cMap::default();
+        map.insert(0x19);
+        map.insert(0x1e);
+        map.insert(0x7f);
+
+        vec![
+            Message::Accel(Vector3 { x: -16384, y: 0, z: 32767 }),
+            Message::Mag(Vector3 { x: 1, y: -1, z: i16::MIN }),
+            Message::Button { pressed: true },
+            Message::Button { pressed: false },
+            Message::Status(Status { dropped: 12, sensor_errors: 0xdead_beef, time: 1_709_211_909 }),
+            Message::Log(Text::from("Hello, big world!")),
+            Message::Log(Text::from("")),
+            Message::Log(long),
+            Message::ScanI2c,
+            Message::SetLed { led: 7, on: true },
+            Message::SetSampleRate { hz: 400 },
+            Message::ReadConfig,
+            Message::SetTime { unix: 946_684_800 },
+            Message::ReadTime,
+            Message::I2cDevices(map),
+            Message::Config(Config { sample_rate_hz: 10, leds: 0b1010_0001 }),
+            Message::Ack { seq: 65535 },
+            Message::Nack { seq: 3, reason: NackReason::Busy },
+            Message::Nack { seq: 4, reason: NackReason::Other(200) },
+            Message::Time { unix: 0 },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        for (i, message) in sample_messages().into_iter().enumerate() {
+            let packet = Packet {
+                seq: 65530u16.wrapping_add(i as u16 * 3),
+                timestamp_ms: 0xffff_fff0u32.wrapping_add(i as u32),
+                message,
+            };
+            let mut buf = [0; MAX_FRAME_LEN];
+            let len = packet.encode(&mut buf).unwrap();
+            assert_eq!(buf[len - 1], 0);
+            assert!(!buf[..len - 1].contains(&0));
+            assert_eq!(Packet::decode(&buf[..len]), Ok(packet));
+            assert_eq!(Packet::decode(&buf[..len - 1]), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn stream_decoding_counts_losses() {
+        let mut encoder = Encoder::new();
+        let mut stream = Vec::new();
+        for (i, message) in sample_messages().into_iter().enumerate() {
+            let mut buf = [0; MAX_FRAME_LEN];
+            let len = encoder.encode(message, i as u32, &mut buf).unwrap();
+            // Drop the third packet on the floor.
+            if i != 2 {
+                stream.extend_from_slice(&buf[..len]);
+            }
+        }
+
+        let mut decoder = Decoder::new();
+        let packets: Vec<Packet> = stream
+            .iter()
+            .filter_map(|&b| decoder.push(b))
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(packets.len(), sample_messages().len() - 1);
+        assert_eq!(packets[2].seq, 3);
+        assert_eq!(packets[2].timestamp_ms, 3);
+        assert_eq!(decoder.lost(), 1);
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let packet = Packet {
+            seq: 7,
+            timestamp_ms: 1234,
+            message: Message::Accel(Vector3 { x: 1, y: 2, z: 3 }),
+        };
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = packet.encode(&mut buf).unwrap();
+
+        for i in 0..len - 1 {
+            let mut bad = buf;
+            bad[i] ^= 0x10;
+            if bad[i] == 0 {
+                continue;
+            }
+            assert!(Packet::decode(&bad[..len]).is_err(), "flipped byte {}", i);
+        }
+    }
+
+    #[test]
+    fn resynchronises_after_garbage() {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = Encoder::new()
+            .encode(Message::Button { pressed: true }, 5, &mut buf)
+            .unwrap();
+
+        let mut decoder = Decoder::new();
+        let garbage = [0x55u8; 300];
+        for &b in garbage.iter() {
+            assert!(decoder.push(b).is_none());
+        }
+        assert_eq!(decoder.push(0), Some(Err(Error::Framing)));
+        let results: Vec<_> = buf[..len].iter().filter_map(|&b| decoder.push(b)).collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].unwrap().message, Message::Button { pressed: true });
+    }
+
+    #[test]
+    fn rejects_unknown_type_and_bad_lengths() {
+        let mut raw = vec![0x7f, 0, 0, 0, 0, 0, 0];
+        let crc = crc16(&raw);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        let mut frame = [0; MAX_FRAME_LEN];
+        let len = cobs::encode(&raw, &mut frame).unwrap();
+        assert_eq!(Packet::decode(&frame[..len]), Err(Error::UnknownType(0x7f)));
+
+        let mut raw = vec![tag::ACCEL, 0, 0, 0, 0, 0, 0, 1, 2];
+        let crc = crc16(&raw);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        let len = cobs::encode(&raw, &mut frame).unwrap();
+        assert_eq!(Packet::decode(&frame[..len]), Err(Error::Payload));
+    }
+
+    #[test]
+    fn i2c_map() {
+        let mut map = I2cMap::default();
+        map.insert(0x00);
+        map.insert(0x19);
+        map.insert(0x1e);
+        assert!(map.contains(0x19));
+        assert!(!map.contains(0x1a));
+        assert!(!map.contains(0xff));
+        assert_eq!(map.iter().collect::<Vec<_>>(), [0x00, 0x19, 0x1e]);
+    }
+
+    #[test]
+    fn text_truncates_on_char_boundary() {
+        let mut text = Text::new();
+        let s = "é".repeat(MAX_TEXT_LEN); // Two bytes each.
+        text.write_str(&s).unwrap();
+        assert!(text.is_full());
+        text.write_str("more").unwrap();
+        assert_eq!(text.as_str().len(), MAX_TEXT_LEN);
+
+        let mut text = Text::from("a");
+        text.write_str(&s).unwrap();
+        assert_eq!(text.as_str().len(), MAX_TEXT_LEN - 1);
+        assert!(!text.is_full());
+    }
+}
@@ -0,0 +1,101 @@
+//! Timer arithmetic for PWM: prescaler and reload values, and the dead-time generator encoding
+//! of the STM32 advanced-control timers (the DTG field of TIMx_BDTR).
+
+/// Prescaler and auto-reload register values that give `freq_hz` from a `clock_hz` timer
+/// clock with as fine a duty resolution as possible.  None if the frequency is 0, faster than
+/// the clock or too slow to reach with 16-bit registers.
+pub fn timing(clock_hz: u32, freq_hz: u32) -> Option<(u16, u16)> {
+    if freq_hz == 0 || freq_hz > clock_hz {
+        return None;
+    }
+    let ticks = clock_hz / freq_hz; // Per period, before the prescaler.
+    let prescale = ticks.div_ceil(0x1_0000).max(1);
+    if prescale > 0x1_0000 {
+        return None;
+    }
+    let reload = ticks / prescale - 1;
+    Some(((prescale - 1) as u16, reload as u16))
+}
+
+/// Timer clock ticks in `dead_ns` nanoseconds, rounded up so the gap is never shorter than asked.
+pub fn dead_time_ticks(dead_ns: u32, clock_hz: u32) -> u32 {
+    ((dead_ns as u64 * clock_hz as u64).div_ceil(1_000_000_000)) as u32
+}
+
+/// DTG value for a dead time of at least `ticks` of the dead-time clock, choosing the shortest
+/// the encoding can express.  None if it's longer than the longest, 1008 ticks.
+///
+/// ``` text
+/// DTG[7:5] = 0xx  DT = DTG[7:0]        x  1 tick    (0 - 127)
+/// DTG[7:5] = 10x  DT = (64 + DTG[5:0]) x  2 ticks   (128 - 254)
+/// DTG[7:5] = 110  DT = (32 + DTG[4:0]) x  8 ticks   (256 - 504)
+/// DTG[7:5] = 111  DT = (32 + DTG[4:0]) x 16 ticks   (512 - 1008)
+/// ```
+pub fn dead_time(ticks: u32) -> Option<u8> {
+    match ticks {
+        0..=127 => Some(ticks as u8),
+        128..=254 => Some(0b1000_0000 | (ticks.div_ceil(2) - 64) as u8),
+        255..=504 => Some(0b1100_0000 | (ticks.div_ceil(8).max(32) - 32) as u8),
+        505..=1008 => Some(0b1110_0000 | (ticks.div_ceil(16) - 32) as u8),
+        _ => None,
+    }
+}
+
+/// Dead time in ticks that a DTG value stands for.
+pub fn dead_time_len(dtg: u8) -> u32 {
+    let dtg = dtg as u32;
+    match dtg >> 5 {
+        0..=3 => dtg,
+        4 | 5 => (64 + (dtg & 0x3f)) * 2,
+        6 => (32 + (dtg & 0x1f)) * 8,
+        _ => (32 + (dtg & 0x1f)) * 16,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timing_values() {
+        // 48 MHz timer clock.
+        assert_eq!(timing(48_000_000, 1000), Some((0, 47_999)));
+        assert_eq!(timing(48_000_000, 500), Some((1, 47_999)));
+        assert_eq!(timing(48_000_000, 20_000), Some((0, 2399)));
+        assert_eq!(timing(48_000_000, 1), Some((732, 65_483)));
+        assert_eq!(timing(8_000_000, 8_000_000), Some((0, 0)));
+        assert_eq!(timing(48_000_000, 0), None);
+        assert_eq!(timing(1000, 2000), None);
+        assert_eq!(timing(u32::MAX, 0), None);
+    }
+
+    #[test]
+    fn ticks_round_up() {
+        assert_eq!(dead_time_ticks(0, 48_000_000), 0);
+        assert_eq!(dead_time_ticks(1000, 48_000_000), 48);
+        assert_eq!(dead_time_ticks(1, 48_000_000), 1);
+        assert_eq!(dead_time_ticks(21, 48_000_000), 2);
+    }
+
+    #[test]
+    fn dead_time_encoding() {
+        assert_eq!(dead_time(0), Some(0));
+        assert_eq!(dead_time(127), Some(127));
+        assert_eq!(dead_time(128), Some(0b1000_0000));
+        assert_eq!(dead_time(1008), Some(0xff));
+        assert_eq!(dead_time(1009), None);
+
+        // Every length encodes to the shortest value at least that long.
+        for ticks in 0..=1008 {
+            let len = dead_time_len(dead_time(ticks).unwrap());
+            assert!(len >= ticks, "{} ticks encoded as {}", ticks, len);
+            let step = match ticks {
+                0..=127 => 1,
+                128..=254 => 2,
+                255..=504 => 8,
+                _ => 16,
+            };
+            assert!(len - ticks < step, "{} ticks encoded as {}", ticks, len);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! The few register operations the interrupt handlers need, as traits.
+//!
+//! The firmware implements them on the PAC's register blocks and `sim` on plain memory, so the
+//! handler bodies in `isr` run unchanged on the chip and in `cargo test`.  Like the PAC's, the
+//! methods take `&self`: registers change under the program's feet and need no `&mut` to write.
+
+/// The EXTI controller's pending register for lines 0 to 31 (PR1).
+pub trait Exti {
+    /// Bit n set if line n has a request pending.
+    fn pending(&self) -> u32;
+    /// Clear the requests on the lines whose bits are set, leaving the others.  (PR1 is
+    /// cleared by writing 1.)
+    fn clear_pending(&self, lines: u32);
+}
+
+/// A timer's update event, the only one the basic timers have.
+pub trait Timer {
+    /// The update interrupt flag (UIF) is set.
+    fn update_pending(&self) -> bool;
+    fn clear_update(&self);
+    /// Set the auto-reload register.  With ARR preload (ARPE) on, the value waits in the
+    /// preload register until the next update event; otherwise it applies at once.
+    fn set_reload(&self, reload: u16);
+    /// Generate an update event (UG): restart the count, load a preloaded reload and set UIF.
+    fn force_update(&self);
+}
+
+/// A GPIO port's outputs.
+pub trait Gpio {
+    /// The output data register, pin n in bit n.
+    fn output(&self) -> u16;
+    /// Write the bit set/reset register: set the pins in the low half, reset those in the
+    /// high half.  Setting wins if a pin is in both.
+    fn set_reset(&self, bits: u32);
+}
@@ -0,0 +1,129 @@
+//! A byte ring buffer, for data that arrives and leaves in chunks of different sizes, such as
+//! USB packets on one side and commands or console text on the other.
+//!
+//! Unlike `spsc::Queue` it has one owner, which keeps it behind whatever lock guards the rest
+//! of its state.
+
+pub struct Ring<const N: usize> {
+    buf: [u8; N],
+    /// Next byte out.
+    head: usize,
+    /// Bytes held.
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    pub const fn new() -> Self {
+        Ring { buf: [0; N], head: 0, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Room for this many more bytes.
+    pub fn space(&self) -> usize {
+        N - self.len
+    }
+
+    /// Add as much of `data` as fits.  Returns how much that was.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let count = data.len().min(self.space());
+        for &byte in &data[..count] {
+            self.buf[(self.head + self.len) % N] = byte;
+            self.len += 1;
+        }
+        count
+    }
+
+    /// Take bytes from the front into `into`.  Returns how many, 0 if there were none.
+    pub fn pop(&mut self, into: &mut [u8]) -> usize {
+        let mut count = 0;
+        while count < into.len() && !self.is_empty() {
+            let front = self.front();
+            let run = front.len().min(into.len() - count);
+            into[count..count + run].copy_from_slice(&front[..run]);
+            self.consume(run);
+            count += run;
+        }
+        count
+    }
+
+    /// The bytes at the front that are contiguous in the buffer: all of them, unless they wrap
+    /// round its end.
+    pub fn front(&self) -> &[u8] {
+        let end = (self.head + self.len).min(N);
+        &self.buf[self.head..end]
+    }
+
+    /// Drop `count` bytes from the front, once they've been dealt with through `front`.
+    pub fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.head = (self.head + count) % N;
+        self.len -= count;
+    }
+}
+
+impl<const N: usize> Default for Ring<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn first_in_first_out() {
+        let mut ring: Ring<8> = Ring::new();
+        assert_eq!(ring.push(b"abc"), 3);
+        assert_eq!(ring.push(b"de"), 2);
+        let mut out = [0; 4];
+        assert_eq!(ring.pop(&mut out), 4);
+        assert_eq!(&out, b"abcd");
+        assert_eq!(ring.pop(&mut out), 1);
+        assert_eq!(out[0], b'e');
+        assert_eq!(ring.pop(&mut out), 0);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn a_full_ring_takes_what_fits() {
+        let mut ring: Ring<4> = Ring::new();
+        assert_eq!(ring.push(b"abcdef"), 4);
+        assert_eq!((ring.len(), ring.space()), (4, 0));
+        assert_eq!(ring.push(b"g"), 0);
+        let mut out = [0; 8];
+        assert_eq!(ring.pop(&mut out), 4);
+        assert_eq!(&out[..4], b"abcd");
+    }
+
+    #[test]
+    fn bytes_wrap_round_the_end() {
+        let mut ring: Ring<4> = Ring::new();
+        ring.push(b"abc");
+        ring.consume(2);
+        assert_eq!(ring.push(b"def"), 3);
+        // "c" and "d" before the end, "ef" after.
+        assert_eq!(ring.front(), b"cd");
+        let mut out = [0; 4];
+        assert_eq!(ring.pop(&mut out), 4);
+        assert_eq!(&out, b"cdef");
+        assert!(ring.front().is_empty());
+    }
+
+    #[test]
+    fn consume_stops_at_the_end_of_the_data() {
+        let mut ring: Ring<4> = Ring::new();
+        ring.push(b"ab");
+        ring.consume(3);
+        assert!(ring.is_empty());
+        ring.push(b"xyz");
+        assert_eq!(ring.front(), b"xy");
+    }
+}
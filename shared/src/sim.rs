@@ -0,0 +1,147 @@
+//! Register blocks in plain memory, standing in for the hardware in host tests of `isr`.
+//! Built for this crate's tests, and for other crates' with the `sim` feature.
+//!
+//! Each implements its trait from `regs` the way the reference manual says the register
+//! behaves, and adds the methods a test needs to play the hardware's part: raising events and
+//! looking at what the handler left behind.
+
+use core::cell::Cell;
+
+use crate::regs::{Exti, Gpio, Timer};
+
+/// EXTI lines 0 to 31, all unmasked.
+#[derive(Default)]
+pub struct SimExti {
+    pending: Cell<u32>,
+}
+
+impl SimExti {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An edge on `line`, as selected by the trigger registers.
+    pub fn edge(&self, line: u32) {
+        self.pending.set(self.pending.get() | 1 << line);
+    }
+
+    /// The NVIC would (still) take the interrupt for `line`.
+    pub fn requesting(&self, line: u32) -> bool {
+        self.pending.get() & 1 << line != 0
+    }
+}
+
+impl Exti for SimExti {
+    fn pending(&self) -> u32 {
+        self.pending.get()
+    }
+
+    fn clear_pending(&self, lines: u32) {
+        self.pending.set(self.pending.get() & !lines);
+    }
+}
+
+/// A 16-bit up-counting timer with its update interrupt enabled.
+#[derive(Default)]
+pub struct SimTimer {
+    update: Cell<bool>,
+    count: Cell<u16>,
+    /// ARR as the counter sees it.
+    reload: Cell<u16>,
+    /// ARR as written, with preload on.
+    preload: Cell<u16>,
+    preload_enabled: Cell<bool>,
+}
+
+impl SimTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set ARPE, so `set_reload` waits for the next update.
+    pub fn enable_preload(&self) {
+        self.preload_enabled.set(true);
+    }
+
+    /// One tick of the counter.  The update event comes on the tick after it matches ARR; if
+    /// ARR was lowered below the count, the counter runs on to 0xFFFF and round first.
+    pub fn tick(&self) {
+        if self.count.get() == self.reload.get() {
+            self.overflow();
+        } else {
+            self.count.set(self.count.get().wrapping_add(1));
+        }
+    }
+
+    /// An update event now: the counter starts over.
+    pub fn overflow(&self) {
+        self.count.set(0);
+        self.update.set(true);
+        if self.preload_enabled.get() {
+            self.reload.set(self.preload.get());
+        }
+    }
+
+    pub fn count(&self) -> u16 {
+        self.count.get()
+    }
+
+    /// The NVIC would (still) take the update interrupt.
+    pub fn requesting(&self) -> bool {
+        self.update.get()
+    }
+
+    pub fn reload(&self) -> u16 {
+        self.reload.get()
+    }
+}
+
+impl Timer for SimTimer {
+    fn update_pending(&self) -> bool {
+        self.update.get()
+    }
+
+    fn clear_update(&self) {
+        self.update.set(false);
+    }
+
+    fn set_reload(&self, reload: u16) {
+        if self.preload_enabled.get() {
+            self.preload.set(reload);
+        } else {
+            self.reload.set(reload);
+        }
+    }
+
+    fn force_update(&self) {
+        self.overflow();
+    }
+}
+
+/// A GPIO port, every pin an output.
+#[derive(Default)]
+pub struct SimGpio {
+    output: Cell<u16>,
+}
+
+impl SimGpio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pin `pin` is driven high.
+    pub fn is_high(&self, pin: u32) -> bool {
+        self.output.get() & 1 << pin != 0
+    }
+}
+
+impl Gpio for SimGpio {
+    fn output(&self) -> u16 {
+        self.output.get()
+    }
+
+    fn set_reset(&self, bits: u32) {
+        let (set, reset) = (bits as u16, (bits >> 16) as u16);
+        self.output.set((self.output.get() & !reset) | set);
+    }
+}
@@ -0,0 +1,293 @@
+//! A fixed-size queue from one producer to one consumer, without locks, for passing events and
+//! samples from an interrupt handler to the main loop.
+//!
+//! A flag only says that something happened since the main loop last looked; a queue keeps
+//! each occurrence and what came with it.  The producer only ever writes the tail index and the
+//! consumer the head, so neither needs a critical section: on the Cortex-M an aligned word
+//! store is atomic, and the orderings make sure a slot is written before it's seen to be full
+//! and read before it's seen to be empty.  That only holds with one of each, so the queue hands
+//! out one `Producer` and one `Consumer`, the first time each is asked for.  An interrupt
+//! handler that is the queue's only producer can take a fresh one each time it runs instead,
+//! with `producer_unchecked`.
+//!
+//! A full queue drops the new item and counts it; `dropped` tells the consumer how many it
+//! missed.
+//!
+//! The size must be a power of two: the indices count items and wrap at `usize::MAX`, which
+//! only a power of two divides, so only then do they keep walking the slots in turn.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+pub struct Queue<T, const N: usize> {
+    /// Items taken so far, written only by the consumer.  Wraps, like `tail`.
+    head: AtomicUsize,
+    /// Items added so far, written only by the producer.
+    tail: AtomicUsize,
+    dropped: AtomicU32,
+    producer_taken: AtomicBool,
+    consumer_taken: AtomicBool,
+    slots: UnsafeCell<MaybeUninit<[T; N]>>,
+}
+
+// Each slot is only touched by the producer while it's free and by the consumer while it's full.
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        const { assert!(N.is_power_of_two(), "a queue's size must be a power of two") };
+        Queue {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU32::new(0),
+            producer_taken: AtomicBool::new(false),
+            consumer_taken: AtomicBool::new(false),
+            slots: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// The queue's one producer, or None if it was handed out already.
+    pub fn producer(&self) -> Option<Producer<'_, T, N>> {
+        (!self.producer_taken.swap(true, Ordering::Relaxed)).then_some(Producer { queue: self })
+    }
+
+    /// A producer, without checking it's the only one.  For an interrupt handler, which can't
+    /// keep the one from `producer` between runs but never runs twice at once.
+    ///
+    /// # Safety
+    ///
+    /// No other producer may be in use while this one is, and `producer` must never be called.
+    pub unsafe fn producer_unchecked(&self) -> Producer<'_, T, N> {
+        Producer { queue: self }
+    }
+
+    /// The queue's one consumer, or None if it was handed out already.
+    pub fn consumer(&self) -> Option<Consumer<'_, T, N>> {
+        (!self.consumer_taken.swap(true, Ordering::Relaxed)).then_some(Consumer { queue: self })
+    }
+
+    /// Items waiting, at least at some moment during the call.
+    pub fn len(&self) -> usize {
+        // Head first, so the tail can't be behind it, and the consumer may have moved on since.
+        let head = self.head.load(Ordering::Acquire);
+        self.tail.load(Ordering::Acquire).wrapping_sub(head).min(N)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Items dropped because the queue was full, since the start.  Wraps.
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn slot(&self, count: usize) -> *mut T {
+        // NOTE(unsafe) in bounds.
+        unsafe { self.slots.get().cast::<T>().add(count % N) }
+    }
+}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        let (head, tail) = (*self.head.get_mut(), *self.tail.get_mut());
+        let mut count = head;
+        while count != tail {
+            // NOTE(unsafe) the items between head and tail were written and never taken.
+            unsafe { self.slot(count).drop_in_place() };
+            count = count.wrapping_add(1);
+        }
+    }
+}
+
+/// The adding end of a `Queue`.
+pub struct Producer<'a, T, const N: usize> {
+    queue: &'a Queue<T, N>,
+}
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    /// Add `item` at the tail, or hand it back and count it as dropped if the queue is full.
+    pub fn enqueue(&mut self, item: T) -> Result<(), T> {
+        let queue = self.queue;
+        let tail = queue.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(queue.head.load(Ordering::Acquire)) == N {
+            queue.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(item);
+        }
+        // NOTE(unsafe) the slot is free, and the consumer won't look at it until the tail moves.
+        unsafe { queue.slot(tail).write(item) };
+        queue.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// There's no room for another item.
+    pub fn is_full(&self) -> bool {
+        self.queue.len() == N
+    }
+}
+
+/// The taking end of a `Queue`.
+pub struct Consumer<'a, T, const N: usize> {
+    queue: &'a Queue<T, N>,
+}
+
+impl<T, const N: usize> Consumer<'_, T, N> {
+    /// Take the item at the head, if any.
+    pub fn dequeue(&mut self) -> Option<T> {
+        let queue = self.queue;
+        let head = queue.head.load(Ordering::Relaxed);
+        if head == queue.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // NOTE(unsafe) the slot is full, and the producer won't touch it until the head moves.
+        let item = unsafe { queue.slot(head).read() };
+        queue.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Items the producer had to drop, since the start.  Wraps.
+    pub fn dropped(&self) -> u32 {
+        self.queue.dropped()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+    use std::thread;
+
+    #[test]
+    fn first_in_first_out() {
+        let queue: Queue<u32, 4> = Queue::new();
+        let (mut producer, mut consumer) = (queue.producer().unwrap(), queue.consumer().unwrap());
+        assert!(queue.producer().is_none());
+        assert!(queue.consumer().is_none());
+
+        assert_eq!(consumer.dequeue(), None);
+        for item in 0..4 {
+            producer.enqueue(item).unwrap();
+        }
+        assert!(producer.is_full());
+        assert_eq!(producer.enqueue(4), Err(4));
+        assert_eq!(producer.enqueue(5), Err(5));
+        assert_eq!((consumer.len(), consumer.dropped()), (4, 2));
+
+        assert_eq!(consumer.dequeue(), Some(0));
+        assert_eq!(consumer.dequeue(), Some(1));
+        producer.enqueue(6).unwrap();
+        let rest: Vec<_> = std::iter::from_fn(|| consumer.dequeue()).collect();
+        assert_eq!(rest, [2, 3, 6]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn indices_wrap() {
+        let queue: Queue<u8, 2> = Queue::new();
+        queue.head.store(usize::MAX - 1, Ordering::Relaxed);
+        queue.tail.store(usize::MAX - 1, Ordering::Relaxed);
+        let (mut producer, mut consumer) = (queue.producer().unwrap(), queue.consumer().unwrap());
+        for item in 0..10 {
+            producer.enqueue(item).unwrap();
+            producer.enqueue(item + 100).unwrap();
+            assert_eq!(consumer.dequeue(), Some(item));
+            assert_eq!(consumer.dequeue(), Some(item + 100));
+        }
+        assert_eq!(consumer.dropped(), 0);
+    }
+
+    #[test]
+    fn a_full_queue_holds_its_items_across_the_wrap() {
+        let queue: Queue<u8, 4> = Queue::new();
+        queue.head.store(usize::MAX - 1, Ordering::Relaxed);
+        queue.tail.store(usize::MAX - 1, Ordering::Relaxed);
+        let (mut producer, mut consumer) = (queue.producer().unwrap(), queue.consumer().unwrap());
+        for item in 0..4 {
+            producer.enqueue(item).unwrap();
+        }
+        assert_eq!(producer.enqueue(4), Err(4));
+        assert_eq!(queue.len(), 4);
+        for item in 0..4 {
+            assert_eq!(consumer.dequeue(), Some(item));
+        }
+        assert_eq!(consumer.dequeue(), None);
+    }
+
+    #[test]
+    fn items_left_behind_are_dropped() {
+        let counted = Rc::new(());
+        let queue: Queue<Rc<()>, 4> = Queue::new();
+        let mut producer = queue.producer().unwrap();
+        for _ in 0..3 {
+            producer.enqueue(counted.clone()).unwrap();
+        }
+        drop(queue.consumer().unwrap().dequeue());
+        assert_eq!(Rc::strong_count(&counted), 3);
+        drop(queue);
+        assert_eq!(Rc::strong_count(&counted), 1);
+    }
+
+    #[test]
+    fn every_item_crosses_between_threads_in_order() {
+        static QUEUE: Queue<u64, 8> = Queue::new();
+        const ITEMS: u64 = 200_000;
+        let mut producer = QUEUE.producer().unwrap();
+        let mut consumer = QUEUE.consumer().unwrap();
+        let sender = thread::spawn(move || {
+            for item in 0..ITEMS {
+                let mut item = item;
+                while let Err(back) = producer.enqueue(item) {
+                    item = back;
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < ITEMS {
+            match consumer.dequeue() {
+                Some(item) => {
+                    assert_eq!(item, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        sender.join().unwrap();
+        assert!(consumer.dequeue().is_none());
+    }
+
+    #[test]
+    fn a_slow_consumer_loses_items_but_counts_them() {
+        static QUEUE: Queue<u32, 16> = Queue::new();
+        const ITEMS: u32 = 100_000;
+        let mut producer = QUEUE.producer().unwrap();
+        let mut consumer = QUEUE.consumer().unwrap();
+        let sender = thread::spawn(move || {
+            for item in 0..ITEMS {
+                producer.enqueue(item).ok();
+            }
+        });
+        let mut received = Vec::new();
+        while !sender.is_finished() || !consumer.is_empty() {
+            received.extend(consumer.dequeue());
+        }
+        sender.join().unwrap();
+        assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(received.len() as u32 + consumer.dropped(), ITEMS);
+    }
+}
@@ -0,0 +1,148 @@
+//! Keeps track of whether each of a fixed set of tasks is still making progress.
+//!
+//! Each task has a deadline: the longest it may go between check-ins.  The firmware only feeds
+//! the hardware watchdog while `overdue` finds nothing, so one stuck task is enough to reset the
+//! board, and `overdue` says which task it was.  A task that only runs some of the time, such
+//! as start-up, is watched only between `watch` and `ignore`.
+//!
+//! Times are `Instant`s, so deadlines must stay well under the 35 minutes they can span.
+
+use crate::time::{Duration, Instant};
+
+pub struct Supervisor<const N: usize> {
+    deadlines: [Duration; N],
+    last: [Instant; N],
+    watched: [bool; N],
+}
+
+impl<const N: usize> Supervisor<N> {
+    /// Task n must check in at least every `deadlines[n]`, counting from `now`.
+    pub fn new(deadlines: [Duration; N], now: Instant) -> Self {
+        Supervisor {
+            deadlines,
+            last: [now; N],
+            watched: [true; N],
+        }
+    }
+
+    /// Watch `task` again, its deadline counting from `now`.  Every task is watched to begin
+    /// with.
+    pub fn watch(&mut self, task: usize, now: Instant) {
+        if task < N {
+            self.watched[task] = true;
+            self.last[task] = now;
+        }
+    }
+
+    /// Stop watching `task`, which is never overdue until `watch` is called for it again.
+    pub fn ignore(&mut self, task: usize) {
+        if let Some(watched) = self.watched.get_mut(task) {
+            *watched = false;
+        }
+    }
+
+    /// Task `task` is still making progress.
+    pub fn check_in(&mut self, task: usize, now: Instant) {
+        if let Some(last) = self.last.get_mut(task) {
+            *last = now;
+        }
+    }
+
+    /// The task that has gone furthest past its deadline, if any has, and by how much.
+    pub fn overdue(&self, now: Instant) -> Option<(usize, Duration)> {
+        (0..N)
+            .filter(|&task| self.watched[task])
+            .filter_map(|task| {
+                let late = (now - self.last[task]).checked_sub(self.deadlines[task])?;
+                Some((task, late)).filter(|_| late > Duration::ZERO)
+            })
+            .max_by_key(|&(_, late)| late)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(ms: u32) -> Instant {
+        Instant::from_micros(ms * 1000)
+    }
+
+    fn supervisor() -> Supervisor<3> {
+        let deadlines = [Duration::from_millis(100), Duration::from_millis(500), Duration::from_millis(1000)];
+        Supervisor::new(deadlines, at(0))
+    }
+
+    #[test]
+    fn healthy_while_tasks_check_in() {
+        let mut s = supervisor();
+        for t in (0..5000).step_by(50) {
+            for task in 0..3 {
+                s.check_in(task, at(t));
+            }
+            assert_eq!(s.overdue(at(t + 49)), None);
+        }
+        // Exactly on the deadline still counts as on time.
+        assert_eq!(s.overdue(at(4950 + 100)), None);
+    }
+
+    #[test]
+    fn reports_the_task_that_missed_its_deadline() {
+        let mut s = supervisor();
+        for t in (0..2000).step_by(50) {
+            s.check_in(0, at(t));
+            if t < 600 {
+                s.check_in(1, at(t));
+            }
+            s.check_in(2, at(t));
+        }
+        // Task 1 last checked in at 550 ms, and was due again by 1050.
+        assert_eq!(s.overdue(at(1000)), None);
+        assert_eq!(s.overdue(at(1950)), Some((1, Duration::from_millis(900))));
+    }
+
+    #[test]
+    fn the_most_overdue_task_wins() {
+        let s = supervisor();
+        // Nothing has checked in since the start: task 0 is 1900 ms late, task 2 only 1000.
+        assert_eq!(s.overdue(at(2000)).map(|(task, _)| task), Some(0));
+        let mut s = supervisor();
+        s.check_in(0, at(1950));
+        s.check_in(2, at(0));
+        assert_eq!(s.overdue(at(2000)), Some((1, Duration::from_millis(1500))));
+    }
+
+    #[test]
+    fn ignored_tasks_are_never_overdue() {
+        let mut s = supervisor();
+        s.ignore(0);
+        s.ignore(1);
+        s.check_in(2, at(1500));
+        assert_eq!(s.overdue(at(1900)), None);
+        // Watched again, task 0 has its whole deadline from then.
+        s.watch(0, at(1900));
+        assert_eq!(s.overdue(at(2000)), None);
+        assert_eq!(s.overdue(at(2050)), Some((0, Duration::from_millis(50))));
+    }
+
+    #[test]
+    fn unknown_tasks_are_ignored() {
+        let mut s = supervisor();
+        s.check_in(3, at(10));
+        s.watch(3, at(10));
+        s.ignore(3);
+        assert_eq!(s.overdue(at(50)), None);
+    }
+
+    #[test]
+    fn across_the_timer_wrap() {
+        let start = Instant::from_micros(u32::MAX - 100_000);
+        let mut s = Supervisor::new([Duration::from_millis(200)], start);
+        s.check_in(0, start + Duration::from_millis(150));
+        assert_eq!(s.overdue(start + Duration::from_millis(300)), None);
+        assert_eq!(
+            s.overdue(start + Duration::from_millis(400)),
+            Some((0, Duration::from_millis(50)))
+        );
+    }
+}
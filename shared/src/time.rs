@@ -0,0 +1,247 @@
+//! Points in time and spans of time, in microseconds, from a free-running 32-bit counter.
+//!
+//! The counter wraps every 2^32 µs, about 71.6 minutes, so an `Instant` only means something
+//! relative to other instants less than half that apart (35.8 minutes).  Within that window,
+//! comparisons and differences are correct across the wrap: `later - earlier` and
+//! `earlier < later` do what they look like even if the counter rolled over in between.
+//! Durations are limited to the same 71.6 minutes.
+//!
+//! Where the counter comes from is up to the target; the firmware reads TIM2.
+
+use core::cmp::Ordering;
+use core::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Instant {
+    micros: u32,
+}
+
+impl Instant {
+    /// The instant a counter reading of `micros` stands for.
+    pub const fn from_micros(micros: u32) -> Self {
+        Instant { micros }
+    }
+
+    /// The raw counter reading.
+    pub const fn as_micros(self) -> u32 {
+        self.micros
+    }
+
+    /// Time from `earlier` to `self`, or None if `earlier` is actually later.
+    pub fn checked_duration_since(self, earlier: Instant) -> Option<Duration> {
+        let diff = self.micros.wrapping_sub(earlier.micros);
+        if (diff as i32) < 0 {
+            None
+        } else {
+            Some(Duration::from_micros(diff))
+        }
+    }
+
+    /// Time from `earlier` to `self`, or zero if `earlier` is actually later.
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Instants order by which came first, assuming they are less than half the wrap period apart.
+impl PartialOrd for Instant {
+    fn partial_cmp(&self, other: &Instant) -> Option<Ordering> {
+        Some((self.micros.wrapping_sub(other.micros) as i32).cmp(&0))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant::from_micros(self.micros.wrapping_add(rhs.micros))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant::from_micros(self.micros.wrapping_sub(rhs.micros))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+/// Same as `duration_since`.
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// A span of time, up to `Duration::MAX` (about 71.6 minutes).  The constructors saturate at
+/// that; arithmetic that overflows or goes below zero panics in debug builds, like integers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration {
+    micros: u32,
+}
+
+impl Duration {
+    pub const ZERO: Duration = Duration::from_micros(0);
+    pub const MAX: Duration = Duration::from_micros(u32::MAX);
+
+    pub const fn from_micros(micros: u32) -> Self {
+        Duration { micros }
+    }
+
+    pub const fn from_millis(millis: u32) -> Self {
+        Duration::from_micros(millis.saturating_mul(1000))
+    }
+
+    pub const fn from_secs(secs: u32) -> Self {
+        Duration::from_micros(secs.saturating_mul(1_000_000))
+    }
+
+    pub const fn as_micros(self) -> u32 {
+        self.micros
+    }
+
+    /// Whole milliseconds, rounded down.
+    pub const fn as_millis(self) -> u32 {
+        self.micros / 1000
+    }
+
+    /// Whole seconds, rounded down.
+    pub const fn as_secs(self) -> u32 {
+        self.micros / 1_000_000
+    }
+
+    pub fn checked_add(self, rhs: Duration) -> Option<Duration> {
+        self.micros.checked_add(rhs.micros).map(Duration::from_micros)
+    }
+
+    pub fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        self.micros.checked_sub(rhs.micros).map(Duration::from_micros)
+    }
+
+    pub fn saturating_sub(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(rhs.micros))
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.micros + rhs.micros)
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.micros - rhs.micros)
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Mul<u32> for Duration {
+    type Output = Duration;
+
+    fn mul(self, rhs: u32) -> Duration {
+        Duration::from_micros(self.micros * rhs)
+    }
+}
+
+impl Div<u32> for Duration {
+    type Output = Duration;
+
+    fn div(self, rhs: u32) -> Duration {
+        Duration::from_micros(self.micros / rhs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Duration::from_millis(3).as_micros(), 3000);
+        assert_eq!(Duration::from_secs(2), Duration::from_millis(2000));
+        assert_eq!(Duration::from_micros(1999).as_millis(), 1);
+        assert_eq!(Duration::from_micros(2_999_999).as_secs(), 2);
+        assert_eq!(Duration::from_secs(5000), Duration::MAX);
+    }
+
+    #[test]
+    fn duration_arithmetic() {
+        let d = Duration::from_millis(10);
+        assert_eq!(d + d, Duration::from_millis(20));
+        assert_eq!(d * 3 - d, Duration::from_millis(20));
+        assert_eq!(d / 4, Duration::from_micros(2500));
+        assert_eq!(d.checked_sub(d * 2), None);
+        assert_eq!(d.saturating_sub(d * 2), Duration::ZERO);
+        assert_eq!(Duration::MAX.checked_add(d), None);
+        assert!(d < d * 2);
+    }
+
+    #[test]
+    fn instants_without_wrap() {
+        let a = Instant::from_micros(1000);
+        let b = a + Duration::from_millis(5);
+        assert_eq!(b.as_micros(), 6000);
+        assert_eq!(b - a, Duration::from_millis(5));
+        assert_eq!(a - b, Duration::ZERO);
+        assert_eq!(a.checked_duration_since(b), None);
+        assert!(a < b);
+        assert!(b > a);
+        assert_eq!(b - Duration::from_millis(5), a);
+    }
+
+    #[test]
+    fn instants_across_wrap() {
+        let before = Instant::from_micros(u32::MAX - 499);
+        let after = before + Duration::from_millis(1);
+        assert_eq!(after.as_micros(), 500);
+        assert!(before < after);
+        assert!(after > before);
+        assert_eq!(after - before, Duration::from_millis(1));
+        assert_eq!(after.checked_duration_since(before), Some(Duration::from_millis(1)));
+        assert_eq!(before.checked_duration_since(after), None);
+
+        let mut deadline = before;
+        deadline += Duration::from_micros(600);
+        assert!(deadline < after);
+        deadline -= Duration::from_micros(600);
+        assert_eq!(deadline, before);
+    }
+
+    #[test]
+    fn half_range_limit() {
+        // Just under half the wrap period apart, the order is still right; beyond it, it flips.
+        let a = Instant::from_micros(0);
+        assert!(a + Duration::from_micros(i32::MAX as u32) > a);
+        assert!(a + Duration::from_micros(i32::MAX as u32 + 1) < a);
+    }
+}
@@ -0,0 +1,347 @@
+//! Software timers on a hashed timing wheel, driven by ticks from one hardware timer.
+//!
+//! Each timer lives in the slot for `deadline % SLOTS`, in a list of the timers there.  Each
+//! tick visits one slot and fires the timers there whose deadline is now, so the cost of a tick
+//! depends on how many timers share the slot, not on how many there are.  Timers further away
+//! than `SLOTS` ticks just stay put for another round.
+//!
+//! The hardware interrupt only counts ticks and the application hands them over with
+//! `advance`; `poll` then returns the expired timers one at a time, so their work runs outside
+//! the interrupt and may schedule or cancel timers as it goes.  Timers that expire on the same
+//! tick come out in the order they were scheduled.  A periodic timer's next deadline is its
+//! last deadline plus the period, not the time it was polled plus the period, so however late
+//! the polling it never drifts.
+
+/// Refers to a scheduled timer.  Stays unique after the timer is gone, so a stale handle can't
+/// cancel a timer that took over its place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerId {
+    index: u16,
+    generation: u16,
+}
+
+/// There is no room for another timer.
+#[derive(Debug, PartialEq)]
+pub struct Full;
+
+struct Entry<T> {
+    deadline: u32,
+    /// Ticks between firings, or 0 for a one-shot.
+    period: u32,
+    payload: T,
+    /// Order of scheduling, which breaks ties between timers due on the same tick.
+    seq: u32,
+    next: Option<u16>,
+}
+
+/// Up to `N` timers carrying a `T` each, on a wheel of `SLOTS` slots.
+pub struct Wheel<T, const N: usize, const SLOTS: usize> {
+    entries: [Option<Entry<T>>; N],
+    generations: [u16; N],
+    heads: [Option<u16>; SLOTS],
+    tails: [Option<u16>; SLOTS],
+    /// The tick being fired.
+    now: u32,
+    /// Ticks handed over by `advance` but not fired yet.
+    behind: u32,
+    next_seq: u32,
+}
+
+impl<T: Copy, const N: usize, const SLOTS: usize> Wheel<T, N, SLOTS> {
+    pub fn new() -> Self {
+        assert!(N <= u16::MAX as usize && SLOTS > 0);
+        Wheel {
+            entries: [(); N].map(|_| None),
+            generations: [0; N],
+            heads: [None; SLOTS],
+            tails: [None; SLOTS],
+            now: 0,
+            behind: 0,
+            next_seq: 0,
+        }
+    }
+
+    /// Fire `payload` once, `delay` ticks from now (at least 1).
+    pub fn once(&mut self, delay: u32, payload: T) -> Result<TimerId, Full> {
+        self.schedule(delay.max(1), 0, payload)
+    }
+
+    /// Fire `payload` every `period` ticks (at least 1), starting a period from now.
+    pub fn every(&mut self, period: u32, payload: T) -> Result<TimerId, Full> {
+        let period = period.max(1);
+        self.schedule(period, period, payload)
+    }
+
+    fn schedule(&mut self, delay: u32, period: u32, payload: T) -> Result<TimerId, Full> {
+        let index = self.entries.iter().position(Option::is_none).ok_or(Full)?;
+        self.entries[index] = Some(Entry {
+            deadline: self.now.wrapping_add(delay),
+            period,
+            payload,
+            seq: self.next_seq,
+            next: None,
+        });
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.link(index as u16);
+        Ok(TimerId {
+            index: index as u16,
+            generation: self.generations[index],
+        })
+    }
+
+    /// Stop a timer.  Returns its payload, or None if it already fired (one-shot) or was
+    /// cancelled before.
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        if !self.is_scheduled(id) {
+            return None;
+        }
+        self.unlink(id.index);
+        self.free(id.index)
+    }
+
+    /// True if the timer will still fire.
+    pub fn is_scheduled(&self, id: TimerId) -> bool {
+        let index = id.index as usize;
+        index < N && self.entries[index].is_some() && self.generations[index] == id.generation
+    }
+
+    /// Ticks until the timer fires next, counting from the last tick handed to `advance`.
+    pub fn remaining(&self, id: TimerId) -> Option<u32> {
+        if !self.is_scheduled(id) {
+            return None;
+        }
+        let deadline = self.entries[id.index as usize].as_ref()?.deadline;
+        Some(deadline.wrapping_sub(self.now.wrapping_add(self.behind)))
+    }
+
+    /// Number of timers scheduled.
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Note that `ticks` more ticks have passed.
+    pub fn advance(&mut self, ticks: u32) {
+        self.behind = self.behind.saturating_add(ticks);
+    }
+
+    /// The next timer to fire, in order, up to the last tick handed to `advance`.
+    /// Periodic timers are rescheduled, one-shots removed.
+    pub fn poll(&mut self) -> Option<(TimerId, T)> {
+        loop {
+            // The earliest scheduled of the timers due now.
+            let mut due: Option<(u16, u32)> = None;
+            let mut cursor = self.heads[self.now as usize % SLOTS];
+            while let Some(index) = cursor {
+                let entry = self.entries[index as usize].as_ref().unwrap();
+                if entry.deadline == self.now && due.is_none_or(|(_, seq)| (entry.seq.wrapping_sub(seq) as i32) < 0) {
+                    due = Some((index, entry.seq));
+                }
+                cursor = entry.next;
+            }
+            if let Some((index, _)) = due {
+                return Some(self.fire(index));
+            }
+            if self.behind == 0 {
+                return None;
+            }
+            self.behind -= 1;
+            self.now = self.now.wrapping_add(1);
+        }
+    }
+
+    fn fire(&mut self, index: u16) -> (TimerId, T) {
+        let id = TimerId {
+            index,
+            generation: self.generations[index as usize],
+        };
+        self.unlink(index);
+        let entry = self.entries[index as usize].as_mut().unwrap();
+        let payload = entry.payload;
+        if entry.period == 0 {
+            self.free(index);
+        } else {
+            entry.deadline = entry.deadline.wrapping_add(entry.period);
+            self.link(index);
+        }
+        (id, payload)
+    }
+
+    fn free(&mut self, index: u16) -> Option<T> {
+        let i = index as usize;
+        self.generations[i] = self.generations[i].wrapping_add(1);
+        self.entries[i].take().map(|entry| entry.payload)
+    }
+
+    /// Add an entry at the end of its slot's list.
+    fn link(&mut self, index: u16) {
+        let entry = self.entries[index as usize].as_mut().unwrap();
+        entry.next = None;
+        let slot = entry.deadline as usize % SLOTS;
+        match self.tails[slot] {
+            Some(tail) => self.entries[tail as usize].as_mut().unwrap().next = Some(index),
+            None => self.heads[slot] = Some(index),
+        }
+        self.tails[slot] = Some(index);
+    }
+
+    /// Take an entry out of its slot's list.
+    fn unlink(&mut self, index: u16) {
+        let entry = self.entries[index as usize].as_ref().unwrap();
+        let (slot, next) = (entry.deadline as usize % SLOTS, entry.next);
+        let mut prev = None;
+        let mut cursor = self.heads[slot];
+        while let Some(i) = cursor {
+            if i == index {
+                break;
+            }
+            prev = cursor;
+            cursor = self.entries[i as usize].as_ref().unwrap().next;
+        }
+        match prev {
+            Some(p) => self.entries[p as usize].as_mut().unwrap().next = next,
+            None => self.heads[slot] = next,
+        }
+        if self.tails[slot] == Some(index) {
+            self.tails[slot] = prev;
+        }
+    }
+}
+
+impl<T: Copy, const N: usize, const SLOTS: usize> Default for Wheel<T, N, SLOTS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Advance one tick at a time and collect what fires, with the tick it fired on.
+    fn run<const N: usize, const S: usize>(wheel: &mut Wheel<char, N, S>, ticks: u32) -> Vec<(u32, char)> {
+        let mut fired = Vec::new();
+        for t in 1..=ticks {
+            wheel.advance(1);
+            while let Some((_, c)) = wheel.poll() {
+                fired.push((t, c));
+            }
+        }
+        fired
+    }
+
+    #[test]
+    fn one_shots_fire_in_deadline_order() {
+        let mut wheel = Wheel::<char, 8, 4>::new();
+        wheel.once(5, 'c').unwrap();
+        wheel.once(1, 'a').unwrap();
+        wheel.once(3, 'b').unwrap();
+        wheel.once(9, 'd').unwrap(); // Same slot as 'a' and 'c', two rounds later.
+        assert_eq!(run(&mut wheel, 12), vec![(1, 'a'), (3, 'b'), (5, 'c'), (9, 'd')]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn same_tick_fires_in_scheduling_order() {
+        let mut wheel = Wheel::<char, 8, 4>::new();
+        for c in "xyz".chars() {
+            wheel.once(2, c).unwrap();
+        }
+        wheel.every(1, 'p').unwrap();
+        assert_eq!(
+            run(&mut wheel, 2),
+            vec![(1, 'p'), (2, 'x'), (2, 'y'), (2, 'z'), (2, 'p')]
+        );
+    }
+
+    #[test]
+    fn periodic_timers_dont_drift() {
+        let mut wheel = Wheel::<char, 8, 16>::new();
+        wheel.every(7, 'a').unwrap();
+        wheel.every(100, 'b').unwrap(); // Longer than the wheel.
+        // Poll late and in uneven steps: the firings still land on multiples of the period.
+        let mut fired = Vec::new();
+        let mut t = 0;
+        for step in [1, 13, 2, 40, 99, 3, 250, 92].iter().cycle().take(40) {
+            wheel.advance(*step);
+            t += step;
+            while let Some((_, c)) = wheel.poll() {
+                fired.push(c);
+            }
+        }
+        assert_eq!(fired.iter().filter(|&&c| c == 'a').count() as u32, t / 7);
+        assert_eq!(fired.iter().filter(|&&c| c == 'b').count() as u32, t / 100);
+    }
+
+    #[test]
+    fn catching_up_keeps_order() {
+        let mut wheel = Wheel::<char, 8, 4>::new();
+        wheel.every(2, 'a').unwrap();
+        wheel.every(3, 'b').unwrap();
+        wheel.advance(6);
+        let fired: Vec<char> = core::iter::from_fn(|| wheel.poll().map(|(_, c)| c)).collect();
+        assert_eq!(fired, vec!['a', 'b', 'a', 'a', 'b']);
+        assert_eq!(wheel.poll(), None);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut wheel = Wheel::<char, 8, 4>::new();
+        let a = wheel.once(2, 'a').unwrap();
+        let b = wheel.every(2, 'b').unwrap();
+        let c = wheel.once(2, 'c').unwrap();
+        assert_eq!(wheel.cancel(b), Some('b'));
+        assert_eq!(wheel.cancel(b), None);
+        assert!(!wheel.is_scheduled(b));
+        assert_eq!(wheel.remaining(a), Some(2));
+        assert_eq!(run(&mut wheel, 4), vec![(2, 'a'), (2, 'c')]);
+        assert_eq!(wheel.cancel(a), None);
+
+        // A stale handle doesn't touch the timer that reused its place.
+        let d = wheel.once(1, 'd').unwrap();
+        assert_eq!(wheel.cancel(a), None);
+        assert_eq!(wheel.cancel(c), None);
+        assert!(wheel.is_scheduled(d));
+    }
+
+    #[test]
+    fn timers_can_be_changed_while_polling() {
+        let mut wheel = Wheel::<char, 4, 4>::new();
+        let tick = wheel.every(1, 't').unwrap();
+        wheel.once(3, 's').unwrap();
+        let mut fired = Vec::new();
+        for _ in 0..6 {
+            wheel.advance(1);
+            while let Some((_, c)) = wheel.poll() {
+                fired.push(c);
+                if c == 's' {
+                    // Stop the periodic timer and start a one-shot relative to this tick.
+                    wheel.cancel(tick);
+                    wheel.once(2, 'e').unwrap();
+                }
+            }
+        }
+        assert_eq!(fired, vec!['t', 't', 't', 's', 'e']);
+    }
+
+    #[test]
+    fn full() {
+        let mut wheel = Wheel::<char, 2, 4>::new();
+        wheel.once(1, 'a').unwrap();
+        wheel.once(1, 'b').unwrap();
+        assert_eq!(wheel.once(1, 'c'), Err(Full));
+        assert_eq!(wheel.len(), 2);
+    }
+
+    #[test]
+    fn across_tick_counter_wrap() {
+        let mut wheel = Wheel::<char, 4, 8>::new();
+        wheel.now = u32::MAX - 2;
+        wheel.every(2, 'a').unwrap();
+        wheel.once(5, 'b').unwrap();
+        assert_eq!(run(&mut wheel, 6), vec![(2, 'a'), (4, 'a'), (5, 'b'), (6, 'a')]);
+    }
+}